@@ -0,0 +1,59 @@
+//! Lets the examples run on either port.
+//!
+//! `boot!(setup)` defines the entry point of the port the example is built
+//! for, it initializes the kernel, calls `setup` to create the example's
+//! processes and starts the scheduler. `info!` logs with `defmt` on the
+//! RP2040 and prints to stdout on the host.
+
+#[cfg(feature = "rp2040")]
+use panic_probe as _;
+
+#[cfg(feature = "rp2040")]
+#[link_section = ".boot_loader"]
+#[used]
+pub static BOOT_LOADER: [u8; 256] = rp2040_boot2::BOOT_LOADER_W25Q080;
+
+#[cfg(feature = "rp2040")]
+macro_rules! boot {
+    ($setup:path) => {
+        #[alkyn::rt::entry]
+        fn main() -> ! {
+            // Load in peripherals
+            let mut pac = rp2040_hal::pac::Peripherals::take().unwrap();
+            let mut m_pac = cortex_m::Peripherals::take().unwrap();
+
+            // Let alkyn init them, we don't init from within the Kernel
+            // so they can be safely used outside
+            alkyn::init(pac.TIMER, &mut pac.RESETS);
+            $setup();
+
+            // Start the OS
+            alkyn::start(&mut m_pac.SYST, 80_000)
+        }
+    };
+}
+
+#[cfg(feature = "hosted")]
+macro_rules! boot {
+    ($setup:path) => {
+        fn main() {
+            alkyn::init();
+            $setup();
+            alkyn::start(&mut alkyn::port::Systick, 80_000)
+        }
+    };
+}
+
+#[cfg(feature = "rp2040")]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        defmt::info!($($arg)*)
+    };
+}
+
+#[cfg(feature = "hosted")]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        println!($($arg)*)
+    };
+}
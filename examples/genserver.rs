@@ -1,59 +1,56 @@
-#![no_std]
-#![no_main]
-#![feature(default_alloc_error_handler)]
+//! A counter server and a client casting to and calling it.
+//!
+//! Run with `cargo run --example genserver` on an RP2040, or with
+//! `cargo run-hosted --example genserver` on the host.
+#![cfg_attr(feature = "rp2040", no_std)]
+#![cfg_attr(feature = "rp2040", no_main)]
+#![cfg_attr(feature = "rp2040", feature(default_alloc_error_handler))]
 
-use alkyn::{rt::entry, genserver::GenServer};
-use panic_probe as _;
+#[macro_use]
+mod common;
 
-use hal::pac;
-use rp2040_hal as hal;
+use alkyn::genserver::{self, GenServer};
+use alkyn::thread::{self, ExitReason, Stack};
 
-use defmt::todo;
-extern crate alloc;
+boot!(setup);
 
+/// Counts what it is cast, a call returns the count so far.
+struct ExampleGenserver {
+    start: u32,
+}
 
-use alkyn::genserver;
-
-#[link_section = ".boot_loader"]
-#[used]
-pub static BOOT_LOADER: [u8; 256] = rp2040_boot2::BOOT_LOADER_W25Q080;
-
-struct ExampleGenserver {}
 impl GenServer for ExampleGenserver {
-    fn handle_call<S>(request: alloc::boxed::Box<dyn core::any::Any>, from: usize, state: S) -> genserver::Reply<S> {
-        todo!()
+    type State = u32;
+    type Call = ();
+    type CallReply = u32;
+    type Cast = u32;
+
+    fn get_name() -> &'static str {
+        "Example genserver"
     }
 
-    fn handle_cast<S>(request: alloc::boxed::Box<dyn core::any::Any>, from: usize, state: S) -> genserver::Reply<S> {
-        todo!()
+    fn init(self) -> Result<u32, ExitReason> {
+        Ok(self.start)
     }
 
-    fn handle_info<S>(request: alloc::boxed::Box<dyn core::any::Any>, from: usize, state: S) -> genserver::Reply<S> {
-        todo!()
+    fn handle_call(_request: (), _from: genserver::From<u32>, count: u32) -> genserver::Reply<u32, u32> {
+        genserver::Reply::Reply(count, count)
     }
 
-    fn get_name() -> &'static str {
-        "Example genserver"
+    fn handle_cast(n: u32, count: u32) -> genserver::NoReply<u32> {
+        genserver::NoReply::NoReply(count + n)
     }
 }
 
-#[entry]
-fn main() -> ! {
-
-    // Load in peripherals
-    let mut pac = pac::Peripherals::take().unwrap();
-    let mut m_pac = cortex_m::Peripherals::take().unwrap();
-
-    // Let alkyn init them, we don't init from within the Kernel
-    // so they can be safely used outside
-    alkyn::init(pac.TIMER, &mut pac.RESETS);
-
-    let eg = ExampleGenserver{};
-    eg.start(1);
-    
-
-    // Start the OS
-    alkyn::start(&mut m_pac.SYST, 80_000)
+fn setup() {
+    let eg = ExampleGenserver { start: 1 };
+    let server = eg.start().expect("could not start genserver");
+    let _ = thread::spawn("client", Stack::Heap(256), move || loop {
+        genserver::cast::<ExampleGenserver>(server, 2).expect("server is gone");
+        let count = genserver::call::<ExampleGenserver>(server, (), 50).expect("server is gone");
+        info!("count: {}", count);
+        thread::sleep(100);
+    });
 }
 
 // End of file
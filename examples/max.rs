@@ -31,7 +31,8 @@ fn main() -> ! {
 
 
     // Create the Stacks for our processes.
-    // Must be static so we can rely on their location in memory.
+    // Must be static so we can rely on their location in memory, 100
+    // stacks of 128 words don't fit in the heap.
     static mut STACKS: [[u32; 128]; 100] = [[0xDEADBEEF; 128]; 100];
 
     // Create processes
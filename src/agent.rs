@@ -0,0 +1,128 @@
+//! # Agents.
+//!
+//! An [`Agent`] is a process that only holds a value. Other processes read
+//! and change it by sending it closures, which the agent runs one at a
+//! time on its value, so no lock is needed around it.
+extern crate alloc;
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+
+use crate::genserver::{self, CallError, From};
+use crate::thread::{self, msg, ExitReason, Pid};
+
+/// What an agent process is asked to do
+enum Op<T> {
+    /// Replace the value with what this makes of it
+    Run(Box<dyn FnOnce(T) -> T>),
+    Stop(ExitReason),
+}
+
+/// A process holding a value of type `T`
+pub struct Agent<T> {
+    pid: Pid,
+    _value: PhantomData<fn(T) -> T>,
+}
+
+impl<T> Clone for Agent<T> {
+    fn clone(&self) -> Agent<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Agent<T> {}
+
+impl<T: 'static> Agent<T> {
+    /// Start an agent registered as `name`, holding what `init` returns.
+    ///
+    /// `init` runs inside the new process. Like a [`GenServer`] the caller
+    /// has to be privileged once the kernel is started, this fails with 2
+    /// otherwise.
+    ///
+    /// [`GenServer`]: crate::genserver::GenServer
+    pub fn start<F>(name: &'static str, init: F) -> Result<Agent<T>, u8>
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let pid = genserver::spawn_server(name, move || run(init()))?;
+        Ok(Agent::from_pid(pid))
+    }
+
+    /// The agent running as `pid`, for example one found in the registry.
+    pub fn from_pid(pid: Pid) -> Agent<T> {
+        Agent {
+            pid,
+            _value: PhantomData,
+        }
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Return what `f` makes of the value, waiting up to `timeout` ticks.
+    pub fn get<R, F>(&self, f: F, timeout: u32) -> Result<R, CallError>
+    where
+        R: 'static,
+        F: FnOnce(&T) -> R + 'static,
+    {
+        self.get_and_update(|value| (f(&value), value), timeout)
+    }
+
+    /// Replace the value with what `f` makes of it, waiting up to
+    /// `timeout` ticks for it to be done.
+    pub fn update<F>(&self, f: F, timeout: u32) -> Result<(), CallError>
+    where
+        F: FnOnce(T) -> T + 'static,
+    {
+        self.get_and_update(|value| ((), f(value)), timeout)
+    }
+
+    /// Run `f` on the value, return the first half of its result and keep
+    /// the second as the new value.
+    pub fn get_and_update<R, F>(&self, f: F, timeout: u32) -> Result<R, CallError>
+    where
+        R: 'static,
+        F: FnOnce(T) -> (R, T) + 'static,
+    {
+        let caller = thread::get_current_pid();
+        genserver::call_with(
+            self.pid,
+            |tag| {
+                let from = From::new(caller, tag);
+                Op::Run(Box::new(move |value| {
+                    let (reply, value) = f(value);
+                    genserver::reply(from, reply);
+                    value
+                }))
+            },
+            Some(timeout),
+        )
+    }
+
+    /// Replace the value with what `f` makes of it, without waiting.
+    pub fn cast<F>(&self, f: F) -> Result<(), msg::SendError>
+    where
+        F: FnOnce(T) -> T + 'static,
+    {
+        let op: Op<T> = Op::Run(Box::new(f));
+        msg::Message::new(op).send(self.pid)?;
+        Ok(())
+    }
+
+    /// Stop the agent with `reason` once it is done with the closures sent
+    /// before, dropping its value.
+    pub fn stop(&self, reason: ExitReason) -> Result<(), msg::SendError> {
+        msg::Message::new(Op::<T>::Stop(reason)).send(self.pid)?;
+        Ok(())
+    }
+}
+
+/// Body of an agent process
+fn run<T: 'static>(mut value: T) -> ExitReason {
+    loop {
+        match *msg::receive_of::<Op<T>>() {
+            Op::Run(f) => value = f(value),
+            Op::Stop(reason) => return reason,
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! # Erlang-like event manager.
+//!
+//! An event manager is a process owning a list of [`EventHandler`]s that
+//! can be added and removed while it runs. Every event sent to it with
+//! [`notify`] or [`sync_notify`] is handed to each handler in the order
+//! they were added.
+//!
+//! A handler that fails an event is removed, the manager and the other
+//! handlers keep running. On the hosted port that includes a handler that
+//! panics, it is terminated with `ExitReason::Error("handler panicked")`.
+//! Panics can not be caught on the target, where they halt the system, so
+//! a handler should report failures through its result instead.
+//!
+//! Managers are started with a name in
+//! [`thread::registry`](crate::thread::registry), so they can be found by
+//! the processes that notify them.
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::genserver::{self, CallError, From, Request};
+use crate::thread::{msg, ExitReason, MonitorRef, Pid};
+
+/// Handles the events of type `E` sent to a manager
+pub trait EventHandler<E>: 'static {
+    /// An error removes the handler from the manager.
+    fn handle_event(&mut self, event: &E) -> Result<(), ExitReason>;
+
+    /// Called when the handler is removed or the manager stops, with why.
+    fn terminate(&mut self, reason: ExitReason) {
+        let _ = reason;
+    }
+}
+
+/// A handler added to a manager, unique within that manager
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct HandlerId(u32);
+
+/// Requests that need an answer from the manager
+enum Call<E> {
+    Add(Box<dyn EventHandler<E>>),
+    Delete(HandlerId),
+    Notify(E),
+    Which,
+}
+
+/// Start an event manager for events of type `E`, registered as `name`.
+pub fn start<E: 'static>(name: &'static str) -> Result<Pid, u8> {
+    genserver::spawn_server(name, run::<E>)
+}
+
+/// Add `handler` to `manager`, it gets every event sent after this.
+pub fn add_handler<E: 'static>(
+    manager: Pid,
+    handler: impl EventHandler<E>,
+    timeout: u32,
+) -> Result<HandlerId, CallError> {
+    genserver::send_call::<Call<E>, E, HandlerId>(manager, Call::Add(Box::new(handler)), timeout)
+}
+
+/// Remove the handler `id` from `manager`, returns whether it was there.
+pub fn delete_handler<E: 'static>(
+    manager: Pid,
+    id: HandlerId,
+    timeout: u32,
+) -> Result<bool, CallError> {
+    genserver::send_call::<Call<E>, E, bool>(manager, Call::Delete(id), timeout)
+}
+
+/// The handlers `manager` runs, in the order they get events.
+pub fn which_handlers<E: 'static>(manager: Pid, timeout: u32) -> Result<Vec<HandlerId>, CallError> {
+    genserver::send_call::<Call<E>, E, Vec<HandlerId>>(manager, Call::Which, timeout)
+}
+
+/// Send `event` to every handler of `manager` without waiting.
+pub fn notify<E: 'static>(manager: Pid, event: E) -> Result<(), msg::SendError> {
+    genserver::send_cast::<Call<E>, E>(manager, event)
+}
+
+/// Send `event` to every handler of `manager` and wait until they all
+/// handled it.
+pub fn sync_notify<E: 'static>(manager: Pid, event: E, timeout: u32) -> Result<(), CallError> {
+    genserver::send_call::<Call<E>, E, ()>(manager, Call::Notify(event), timeout)
+}
+
+/// Stop `manager` with `reason`, removing all of its handlers.
+pub fn stop<E: 'static>(manager: Pid, reason: ExitReason) -> Result<(), msg::SendError> {
+    genserver::send_stop::<Call<E>, E>(manager, reason)
+}
+
+struct Manager<E: 'static> {
+    handlers: Vec<(HandlerId, Box<dyn EventHandler<E>>)>,
+    next_id: u32,
+}
+
+impl<E: 'static> Manager<E> {
+    fn notify(&mut self, event: &E) {
+        self.handlers.retain_mut(|(_, handler)| match handle(handler.as_mut(), event) {
+            Ok(()) => true,
+            Err(reason) => {
+                defmt::debug!("alkyn: removing failed event handler");
+                handler.terminate(reason);
+                false
+            }
+        });
+    }
+
+    /// Serve `call`, every kind answers with its own type.
+    fn call(&mut self, call: Call<E>, from: Pid, tag: MonitorRef) {
+        match call {
+            Call::Add(handler) => {
+                let id = HandlerId(self.next_id);
+                self.next_id = self.next_id.wrapping_add(1);
+                self.handlers.push((id, handler));
+                genserver::reply(From::new(from, tag), id);
+            }
+            Call::Delete(id) => {
+                let found = match self.handlers.iter().position(|(h, _)| *h == id) {
+                    Some(i) => {
+                        let (_, mut handler) = self.handlers.remove(i);
+                        handler.terminate(ExitReason::Normal);
+                        true
+                    }
+                    None => false,
+                };
+                genserver::reply(From::new(from, tag), found);
+            }
+            Call::Notify(event) => {
+                self.notify(&event);
+                genserver::reply(From::new(from, tag), ());
+            }
+            Call::Which => {
+                let ids: Vec<HandlerId> = self.handlers.iter().map(|(id, _)| *id).collect();
+                genserver::reply(From::new(from, tag), ids);
+            }
+        }
+    }
+}
+
+/// Hand `event` to `handler`, turning a panic into a failure where it can
+/// be caught.
+#[cfg(feature = "hosted")]
+fn handle<E: 'static>(handler: &mut dyn EventHandler<E>, event: &E) -> Result<(), ExitReason> {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    // The handler is dropped right after, whatever state it was left in
+    catch_unwind(AssertUnwindSafe(|| handler.handle_event(event)))
+        .unwrap_or(Err(ExitReason::Error("handler panicked")))
+}
+
+#[cfg(not(feature = "hosted"))]
+fn handle<E: 'static>(handler: &mut dyn EventHandler<E>, event: &E) -> Result<(), ExitReason> {
+    handler.handle_event(event)
+}
+
+/// Body of a manager process
+fn run<E: 'static>() -> ExitReason {
+    let mut manager: Manager<E> = Manager {
+        handlers: Vec::new(),
+        next_id: 0,
+    };
+
+    loop {
+        let (from, m) = msg::receive_with_sender();
+        // Anything else is not for the manager
+        let request = match m.downcast::<Request<Call<E>, E>>() {
+            Ok(request) => *request,
+            Err(_) => continue,
+        };
+        match request {
+            Request::Call(tag, call) => manager.call(call, from, tag),
+            Request::Cast(event) => manager.notify(&event),
+            Request::Stop(reason) => {
+                for (_, handler) in manager.handlers.iter_mut() {
+                    handler.terminate(reason);
+                }
+                return reason;
+            }
+        }
+    }
+}
@@ -8,129 +8,418 @@
 //!
 //! In general, GenServers should be treated like state machines.
 //!
+//! An implementation only provides the callbacks: [`init`](GenServer::init)
+//! builds the state, [`handle_call`](GenServer::handle_call) and
+//! [`handle_cast`](GenServer::handle_cast) serve the requests sent with
+//! [`call`] and [`cast`], [`handle_info`](GenServer::handle_info) gets every
+//! other message and [`terminate`](GenServer::terminate) runs when it stops.
+//! Receiving, dispatching and replying is done by the server's process.
+//! It also answers the [`sys`](crate::sys) messages on its own.
+//!
 extern crate alloc;
 use core::any::Any;
+use core::marker::PhantomData;
 
-use crate::thread;
+use crate::sys::{self, System};
+use crate::thread::{self, registry, Down, ExitReason, IntoExitReason, MonitorRef, Pid, Stack};
 use alloc::boxed::Box;
 use crate::thread::msg;
 
-pub enum MessageType {
-    Call,
-    Cast,
-    Info,
-    Destroy,
+/// What a server does after [`handle_call`](GenServer::handle_call)
+pub enum Reply<S, R, C = ()> {
+    /// Send the reply back to the caller
+    Reply(R, S),
+    /// Send the reply, then go on as [`Then`] says
+    ReplyThen(R, S, Then<C>),
+    /// Leave the caller waiting, to answer later with [`reply`]
+    NoReply(S),
+    /// Leave the caller waiting and go on as [`Then`] says
+    NoReplyThen(S, Then<C>),
+    /// Stop the server without replying
+    Stop(ExitReason, S),
+}
+
+/// What a server does after [`handle_cast`](GenServer::handle_cast),
+/// [`handle_info`](GenServer::handle_info) and the other callbacks
+pub enum NoReply<S, C = ()> {
+    NoReply(S),
+    /// Go on as [`Then`] says
+    NoReplyThen(S, Then<C>),
+    Stop(ExitReason, S),
+}
+
+/// What a server does before taking the next message
+pub enum Then<C> {
+    /// Call [`handle_timeout`](GenServer::handle_timeout) if no message
+    /// arrives within this many ticks
+    Timeout(u32),
+    /// Call [`handle_continue`](GenServer::handle_continue) with this
+    /// first
+    Continue(C),
 }
 
-pub enum ReplyType {
-    Reply,
-    NoReply,
-    Stop,
+/// Why a [`call`] got no reply
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum CallError {
+    /// No reply within the timeout
+    Timeout,
+    /// The server is not running, or stopped before replying
+    ServerDown(ExitReason),
 }
 
-pub struct Message {
-    message_type: MessageType,
-    msg: Box<dyn Any>,
-    ptr: usize,
-    from: usize
+/// Requests a server process understands, any other message is info
+pub(crate) enum Request<C, K> {
+    /// Tagged with the caller's monitor on the server
+    Call(MonitorRef, C),
+    Cast(K),
+    Stop(ExitReason),
 }
 
-pub struct Reply<S> {
-    reply_type: ReplyType,
-    state: S,
-    message: Box<dyn Any>,
+/// The caller of a [`call`], needed to answer it.
+///
+/// A server that cannot answer right away keeps it in its state, returns
+/// [`Reply::NoReply`] and answers later with [`reply`].
+pub struct From<R> {
+    pid: Pid,
+    tag: MonitorRef,
+    _reply: PhantomData<fn(R)>,
+}
+
+impl<R> From<R> {
+    pub(crate) fn new(pid: Pid, tag: MonitorRef) -> From<R> {
+        From {
+            pid,
+            tag,
+            _reply: PhantomData,
+        }
+    }
+
+    /// The process waiting for the reply
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+}
+
+/// A reply to a call, with the tag of the call it answers
+struct Response<R> {
+    tag: MonitorRef,
+    reply: R,
 }
 
 /// GenServer implementations
-pub trait GenServer {
-    fn handle_call<S>(request: Box<dyn Any>, from: usize, state: S) -> Reply<S>;
-    fn handle_cast<S>(request: Box<dyn Any>, from: usize, state: S) -> Reply<S>;
-    fn handle_info<S>(request: Box<dyn Any>, from: usize, state: S) -> Reply<S>;
+///
+/// Every [`start`](GenServer::start) spawns a new process with its own
+/// stack, which owns the state until it stops.
+pub trait GenServer: Sized + Send + 'static {
+    type State;
+    type Call: 'static;
+    type CallReply: 'static;
+    type Cast: 'static;
+    /// Argument for [`handle_continue`](GenServer::handle_continue)
+    type Continue = ();
+
     fn get_name() -> &'static str;
 
-    fn start<S: Copy>(self, intial_state: S) -> usize
-    where
-        Self: Sized,
-    {
-        let h = GenServerHandle {
-            state: intial_state,
-            gen_server: self,
-            name: Self::get_name(),
-            ptr: unsafe { core::mem::transmute(0) },
-        };
+    /// Build the initial state, inside the new process.
+    ///
+    /// An error stops the process with that reason.
+    fn init(self) -> Result<Self::State, ExitReason>;
 
-        let idx = h.generate_handler();
+    fn handle_call(
+        request: Self::Call,
+        from: From<Self::CallReply>,
+        state: Self::State,
+    ) -> Reply<Self::State, Self::CallReply, Self::Continue>;
 
-        // Immortalize the handler
-        // Very leaky
-        let b = Box::new(h);
-        let ptr = Box::into_raw(b);
-        unsafe {
-            (*ptr).ptr = ptr;
-        };
+    fn handle_cast(
+        request: Self::Cast,
+        state: Self::State,
+    ) -> NoReply<Self::State, Self::Continue>;
+
+    /// Any message that is not a request, like an [`Exit`](thread::Exit)
+    /// or a [`Down`](thread::Down). Dropped by default.
+    fn handle_info(
+        msg: Box<dyn Any>,
+        from: Pid,
+        state: Self::State,
+    ) -> NoReply<Self::State, Self::Continue> {
+        let _ = (msg, from);
+        NoReply::NoReply(state)
+    }
+
+    /// Asked for with [`Then::Continue`], runs before the next message
+    /// is taken.
+    fn handle_continue(
+        arg: Self::Continue,
+        state: Self::State,
+    ) -> NoReply<Self::State, Self::Continue> {
+        let _ = arg;
+        NoReply::NoReply(state)
+    }
+
+    /// No message arrived within a [`Then::Timeout`].
+    fn handle_timeout(state: Self::State) -> NoReply<Self::State, Self::Continue> {
+        NoReply::NoReply(state)
+    }
 
-        idx
+    /// Called when the server stops on its own or through [`stop`], before
+    /// its state is dropped.
+    fn terminate(reason: ExitReason, state: Self::State) {
+        let _ = (reason, state);
+    }
+
+    /// Start a server process registered under [`get_name`](GenServer::get_name).
+    ///
+    /// A later instance with the same name takes the name over.
+    fn start(self) -> Result<Pid, u8> {
+        spawn_server(Self::get_name(), move || run(self))
+    }
+
+    /// Start a server process that is only reachable through its [`Pid`].
+    fn start_anonymous(self) -> Result<Pid, u8> {
+        unregistered(Self::get_name(), || self.start())
     }
 }
 
-struct GenServerHandle<S: Copy, T: GenServer> {
-    state: S,
-    gen_server: T,
-    name: &'static str,
-    ptr: *mut GenServerHandle<S, T>,
+/// Spawn a process named `name` running the server loop `body`.
+///
+/// Like any spawn, this needs a privileged caller once the kernel is
+/// started, and fails with 2 otherwise.
+pub(crate) fn spawn_server<F, R>(name: &'static str, body: F) -> Result<Pid, u8>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: IntoExitReason,
+{
+    thread::spawn_with_config(
+        name,
+        Stack::Heap(thread::PROCESS_STACK),
+        body,
+        0x1,
+        false,
+        thread::Core::None,
+        msg::MailboxConfig::UNBOUNDED,
+    )
 }
 
-impl<S: Copy, T: GenServer> GenServerHandle<S, T> {
-    pub fn generate_handler(&self) -> usize {
-        static mut STACK: [u32; 1024] = [0; 1024];
-        thread::create_thread_with_config(
-            self.name,
-            unsafe {&mut STACK},
-            Self::handle_msg,
-            0x1,
-            false,
-            thread::Core::None,
-        ).expect("Could not create genserver tcb");
+/// Run `start`, leaving `name` registered to whoever held it before.
+pub(crate) fn unregistered<F>(name: &'static str, start: F) -> Result<Pid, u8>
+where
+    F: FnOnce() -> Result<Pid, u8>,
+{
+    unsafe {
+        let cs = critical_section::acquire();
+        // Creating the thread registers it, hand the name back
+        let prev = registry::lookup_by_name(name);
+        let res = start();
+        if let Ok(pid) = res {
+            registry::unregister_pid(pid);
+        }
+        if let Some(prev) = prev {
+            registry::set_registry_for_pid(prev, name);
+        }
+        critical_section::release(cs);
+        res
+    }
+}
 
-        0
+/// Send `request` to the server `pid` and wait up to `timeout` ticks for
+/// its reply.
+///
+/// The server is monitored for the duration of the call, so it fails
+/// with `ServerDown` as soon as the server stops. A reply that comes in
+/// after the timeout is dropped.
+pub fn call<T: GenServer>(
+    pid: Pid,
+    request: T::Call,
+    timeout: u32,
+) -> Result<T::CallReply, CallError> {
+    send_call::<T::Call, T::Cast, T::CallReply>(pid, request, timeout)
+}
+
+/// [`call`] for any process that serves `Request<C, K>`.
+pub(crate) fn send_call<C: 'static, K: 'static, R: 'static>(
+    pid: Pid,
+    request: C,
+    timeout: u32,
+) -> Result<R, CallError> {
+    call_with(pid, |tag| Request::<C, K>::Call(tag, request), Some(timeout))
+}
+
+/// Send the message `make` builds from the call's tag to `pid`, and wait
+/// up to `timeout` ticks for the [`reply`] with that tag. `None` waits for
+/// as long as `pid` is alive.
+pub(crate) fn call_with<M: 'static, R: 'static>(
+    pid: Pid,
+    make: impl FnOnce(MonitorRef) -> M,
+    timeout: Option<u32>,
+) -> Result<R, CallError> {
+    let tag = thread::monitor(pid);
+    let is_down = |m: &dyn Any| m.downcast_ref::<Down>().map_or(false, |d| d.monitor_ref == tag);
+    if msg::Message::new(make(tag)).send(pid).is_err() {
+        thread::demonitor(tag);
+        let _ = msg::check_receive_matching(is_down);
+        return Err(CallError::ServerDown(ExitReason::NoProc));
     }
 
-    fn handle_msg() -> ! {
-        loop {
-            let m: Result<Box<Message>, _> = thread::msg::receive().downcast();
-
-            match m {
-                Ok(parsed) => {
-                    let handle = <*mut Self>::from_bits(parsed.ptr);
-                    match parsed.message_type {
-                        MessageType::Call => {
-                            let r = <T>::handle_call(parsed.msg, parsed.from, unsafe {(*handle).state});
-                            Self::after_call(handle, r, parsed.from)
-                        },
-                        MessageType::Cast => todo!(),
-                        MessageType::Info => todo!(),
-                        MessageType::Destroy => todo!(),
-                    };
-                },
-                Err(a) => defmt::panic!("alkyn: Genserver received bad msg"),
-            }
+    let answer = |m: &dyn Any| match m.downcast_ref::<Response<R>>() {
+        Some(r) => r.tag == tag,
+        None => is_down(m),
+    };
+    let m = match timeout {
+        Some(ticks) => msg::receive_matching_timeout(answer, ticks),
+        None => Ok(msg::receive_matching(answer)),
+    };
+    // No reply is sent once this is gone, see `reply`
+    thread::demonitor(tag);
+    let m = match m {
+        Ok(m) => m,
+        Err(_) => {
+            // Answered or gone down right after the timeout
+            while msg::check_receive_matching(answer).is_some() {}
+            return Err(CallError::Timeout);
+        }
+    };
+    match m.downcast::<Response<R>>() {
+        Ok(response) => Ok(response.reply),
+        Err(m) => {
+            let reason = m.downcast_ref::<Down>().map_or(ExitReason::NoProc, |d| d.reason);
+            Err(CallError::ServerDown(reason))
         }
     }
+}
+
+/// Answer the call `from` came with.
+///
+/// Nothing happens if the caller stopped waiting or is gone.
+pub fn reply<R: 'static>(from: From<R>, reply: R) {
+    // The caller demonitors when it stops waiting
+    let waiting = || thread::is_monitoring(from.tag);
+    let _ = msg::Message::new(Response { tag: from.tag, reply }).send_if(from.pid, waiting);
+}
+
+/// Send `request` to the server `pid` without waiting.
+pub fn cast<T: GenServer>(pid: Pid, request: T::Cast) -> Result<(), msg::SendError> {
+    send_cast::<T::Call, T::Cast>(pid, request)
+}
+
+/// [`cast`] for any process that serves `Request<C, K>`.
+pub(crate) fn send_cast<C: 'static, K: 'static>(
+    pid: Pid,
+    request: K,
+) -> Result<(), msg::SendError> {
+    msg::Message::new(Request::<C, K>::Cast(request)).send(pid)?;
+    Ok(())
+}
+
+/// Ask the server `pid` to stop with `reason`, once it is done with the
+/// requests before this one.
+pub fn stop<T: GenServer>(pid: Pid, reason: ExitReason) -> Result<(), msg::SendError> {
+    send_stop::<T::Call, T::Cast>(pid, reason)
+}
 
-    fn after_call(handle: *mut Self, reply: Reply<S>, from: usize) {
-        match reply.reply_type {
-            ReplyType::Reply => {
-                msg::Message::new(reply.message).send(from).expect("Failed to reply");
-            },
-            ReplyType::NoReply => (),
-            ReplyType::Stop => unsafe {Self::kill(handle)}
+/// [`stop`] for any process that serves `Request<C, K>`.
+pub(crate) fn send_stop<C: 'static, K: 'static>(
+    pid: Pid,
+    reason: ExitReason,
+) -> Result<(), msg::SendError> {
+    msg::Message::new(Request::<C, K>::Stop(reason)).send(pid)?;
+    Ok(())
+}
+
+/// Body of a server process
+fn run<T: GenServer>(server: T) -> ExitReason {
+    let mut state = match server.init() {
+        Ok(state) => state,
+        Err(reason) => return reason,
+    };
+
+    let mut then = None;
+    // Of a `Then::Timeout`, kept while system messages come in
+    let mut deadline: Option<u32> = None;
+    let mut suspended = false;
+    loop {
+        if suspended {
+            // Ordinary messages wait in the mailbox until resumed
+            let request = msg::receive_of::<System<T::State>>();
+            state = sys::handle(*request, state, &mut suspended);
+            continue;
+        }
+
+        let next = match then.take() {
+            Some(Then::Continue(arg)) => T::handle_continue(arg, state),
+            waiting => {
+                if let Some(Then::Timeout(ticks)) = waiting {
+                    deadline = Some(thread::get_ticks().wrapping_add(ticks));
+                }
+                let received = match deadline {
+                    Some(deadline) => {
+                        let ticks = deadline.wrapping_sub(thread::get_ticks()) as i32;
+                        msg::receive_with_sender_timeout(ticks.max(0) as u32).ok()
+                    }
+                    None => Some(msg::receive_with_sender()),
+                };
+                match received {
+                    None => {
+                        deadline = None;
+                        T::handle_timeout(state)
+                    }
+                    Some((from, m)) => match m.downcast::<System<T::State>>() {
+                        Ok(request) => {
+                            state = sys::handle(*request, state, &mut suspended);
+                            // Not a message for the server, wait again
+                            // until the same deadline
+                            continue;
+                        }
+                        Err(m) => {
+                            deadline = None;
+                            handle_msg::<T>(from, m, state)
+                        }
+                    },
+                }
+            }
+        };
+
+        match next {
+            NoReply::NoReply(next) => state = next,
+            NoReply::NoReplyThen(next, t) => {
+                state = next;
+                then = Some(t);
+            }
+            NoReply::Stop(reason, state) => {
+                T::terminate(reason, state);
+                return reason;
+            }
         }
-        
     }
+}
 
-    unsafe fn kill(handle: *mut Self) {
-        thread::kill_thread(thread::get_current_thread_idx());
-        let b = Box::from_raw(handle);
-        thread::sleep(1);
+/// Hand the message `m` from `from` to the matching callback, replying
+/// to calls.
+fn handle_msg<T: GenServer>(
+    from: Pid,
+    m: Box<dyn Any>,
+    state: T::State,
+) -> NoReply<T::State, T::Continue> {
+    let request = match m.downcast::<Request<T::Call, T::Cast>>() {
+        Ok(request) => *request,
+        Err(m) => return T::handle_info(m, from, state),
+    };
+
+    match request {
+        Request::Call(tag, request) => match T::handle_call(request, From::new(from, tag), state) {
+            Reply::Reply(r, state) => {
+                reply(From::new(from, tag), r);
+                NoReply::NoReply(state)
+            }
+            Reply::ReplyThen(r, state, then) => {
+                reply(From::new(from, tag), r);
+                NoReply::NoReplyThen(state, then)
+            }
+            Reply::NoReply(state) => NoReply::NoReply(state),
+            Reply::NoReplyThen(state, then) => NoReply::NoReplyThen(state, then),
+            Reply::Stop(reason, state) => NoReply::Stop(reason, state),
+        },
+        Request::Cast(request) => T::handle_cast(request, state),
+        Request::Stop(reason) => NoReply::Stop(reason, state),
     }
 }
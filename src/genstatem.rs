@@ -0,0 +1,294 @@
+//! # Erlang-like state machine.
+//!
+//! A GenStateMachine is a process like a [`GenServer`](crate::genserver::GenServer),
+//! but besides its data it keeps an explicit state and hands every request
+//! and message to a single [`handle_event`](GenStateMachine::handle_event)
+//! together with that state. It is used through the same [`call`], [`cast`]
+//! and [`stop`] client API.
+//!
+//! On top of that it offers:
+//! - State enter calls: with `STATE_ENTER` set, every state change is
+//!   followed by an [`Event::Enter`] in the new state.
+//! - Postponing: an event [`postpone`](GenStateMachine::postpone) accepts is
+//!   kept aside and handed over again after the next state change.
+//! - Timeouts, counted in kernel ticks: an event timeout fires when no
+//!   other event arrives in time, a state timeout when the state has not
+//!   changed in time. Both are set on the [`Transition`].
+//!
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use core::any::Any;
+
+use crate::genserver::{self, CallError, From, Request};
+use crate::thread::{self, msg, ExitReason, Pid};
+
+/// What a state machine is handed
+pub enum Event<T: GenStateMachine> {
+    /// A request sent with [`call`], answer it with [`genserver::reply`]
+    Call(T::Call, From<T::CallReply>),
+    /// A request sent with [`cast`]
+    Cast(T::Cast),
+    /// Any other message and who sent it
+    Info(Pid, Box<dyn Any>),
+    /// The state was just entered from the one given, see `STATE_ENTER`
+    Enter(T::State),
+    /// No event arrived within the event timeout
+    EventTimeout,
+    /// The state did not change within the state timeout
+    StateTimeout,
+}
+
+enum Action<S> {
+    Next(S),
+    Keep,
+    Stop(ExitReason),
+}
+
+/// What a state machine does after an event, and the data it goes on with
+pub struct Transition<S, D> {
+    action: Action<S>,
+    data: D,
+    event_timeout: Option<u32>,
+    state_timeout: Option<u32>,
+}
+
+impl<S, D> Transition<S, D> {
+    /// Move to `state`, which may be the current one
+    pub fn next(state: S, data: D) -> Transition<S, D> {
+        Transition::new(Action::Next(state), data)
+    }
+
+    /// Stay in the current state
+    pub fn keep(data: D) -> Transition<S, D> {
+        Transition::new(Action::Keep, data)
+    }
+
+    /// Stop the state machine
+    pub fn stop(reason: ExitReason, data: D) -> Transition<S, D> {
+        Transition::new(Action::Stop(reason), data)
+    }
+
+    /// Fire an [`Event::EventTimeout`] unless another event arrives within
+    /// `ticks`.
+    pub fn event_timeout(mut self, ticks: u32) -> Transition<S, D> {
+        self.event_timeout = Some(ticks);
+        self
+    }
+
+    /// Fire an [`Event::StateTimeout`] unless the state changes within
+    /// `ticks`. Replaces a running state timeout.
+    pub fn state_timeout(mut self, ticks: u32) -> Transition<S, D> {
+        self.state_timeout = Some(ticks);
+        self
+    }
+
+    fn new(action: Action<S>, data: D) -> Transition<S, D> {
+        Transition {
+            action,
+            data,
+            event_timeout: None,
+            state_timeout: None,
+        }
+    }
+}
+
+/// State machine implementations
+///
+/// Every [`start`](GenStateMachine::start) spawns a new process with its
+/// own stack, which owns the state and data until it stops.
+pub trait GenStateMachine: Sized + Send + 'static {
+    type State: Clone + PartialEq;
+    type Data;
+    type Call: 'static;
+    type CallReply: 'static;
+    type Cast: 'static;
+
+    /// Hand an [`Event::Enter`] to the state machine after every state
+    /// change, and after `init`.
+    const STATE_ENTER: bool = false;
+
+    fn get_name() -> &'static str;
+
+    /// Build the initial state and data, inside the new process.
+    ///
+    /// An error stops the process with that reason.
+    fn init(self) -> Result<(Self::State, Self::Data), ExitReason>;
+
+    fn handle_event(
+        event: Event<Self>,
+        state: &Self::State,
+        data: Self::Data,
+    ) -> Transition<Self::State, Self::Data>;
+
+    /// Whether to keep `event` for after the next state change instead of
+    /// handling it now. Only requests and messages can be postponed.
+    fn postpone(event: &Event<Self>, state: &Self::State, data: &Self::Data) -> bool {
+        let _ = (event, state, data);
+        false
+    }
+
+    /// Called when the state machine stops on its own or through [`stop`],
+    /// before its data is dropped.
+    fn terminate(reason: ExitReason, state: Self::State, data: Self::Data) {
+        let _ = (reason, state, data);
+    }
+
+    /// Start a state machine process registered under
+    /// [`get_name`](GenStateMachine::get_name).
+    ///
+    /// A later instance with the same name takes the name over.
+    fn start(self) -> Result<Pid, u8> {
+        genserver::spawn_server(Self::get_name(), move || run(self))
+    }
+
+    /// Start a state machine process that is only reachable through its
+    /// [`Pid`].
+    fn start_anonymous(self) -> Result<Pid, u8> {
+        genserver::unregistered(Self::get_name(), || self.start())
+    }
+}
+
+/// Send `request` to the state machine `pid` and wait up to `timeout`
+/// ticks for its reply, see [`genserver::call`].
+pub fn call<T: GenStateMachine>(
+    pid: Pid,
+    request: T::Call,
+    timeout: u32,
+) -> Result<T::CallReply, CallError> {
+    genserver::send_call::<T::Call, T::Cast, T::CallReply>(pid, request, timeout)
+}
+
+/// Send `request` to the state machine `pid` without waiting.
+pub fn cast<T: GenStateMachine>(pid: Pid, request: T::Cast) -> Result<(), msg::SendError> {
+    genserver::send_cast::<T::Call, T::Cast>(pid, request)
+}
+
+/// Ask the state machine `pid` to stop with `reason`, once it is done with
+/// the requests before this one.
+pub fn stop<T: GenStateMachine>(pid: Pid, reason: ExitReason) -> Result<(), msg::SendError> {
+    genserver::send_stop::<T::Call, T::Cast>(pid, reason)
+}
+
+/// Whether the tick count `now` has reached `deadline`, across wrap-arounds
+fn reached(deadline: u32, now: u32) -> bool {
+    now.wrapping_sub(deadline) as i32 >= 0
+}
+
+/// What the process waits for next
+enum Next<T: GenStateMachine> {
+    Event(Event<T>),
+    Stop(ExitReason),
+}
+
+/// Wait for the next message until `deadline`, if there is one.
+fn next_message<T: GenStateMachine>(deadline: Option<u32>) -> Option<Next<T>> {
+    let (from, m) = match deadline {
+        None => msg::receive_with_sender(),
+        Some(deadline) => {
+            let ticks = deadline.wrapping_sub(thread::get_ticks()) as i32;
+            msg::receive_with_sender_timeout(ticks.max(0) as u32).ok()?
+        }
+    };
+
+    let next = match m.downcast::<Request<T::Call, T::Cast>>() {
+        Ok(request) => match *request {
+            Request::Call(tag, request) => Next::Event(Event::Call(request, From::new(from, tag))),
+            Request::Cast(request) => Next::Event(Event::Cast(request)),
+            Request::Stop(reason) => Next::Stop(reason),
+        },
+        Err(m) => Next::Event(Event::Info(from, m)),
+    };
+    Some(next)
+}
+
+/// Body of a state machine process
+fn run<T: GenStateMachine>(machine: T) -> ExitReason {
+    let (mut state, mut data) = match machine.init() {
+        Ok(init) => init,
+        Err(reason) => return reason,
+    };
+
+    // Events to hand over before taking new messages
+    let mut queue: VecDeque<Event<T>> = VecDeque::new();
+    // Events waiting for the next state change
+    let mut postponed: VecDeque<Event<T>> = VecDeque::new();
+    let mut event_deadline: Option<u32> = None;
+    let mut state_deadline: Option<u32> = None;
+
+    if T::STATE_ENTER {
+        queue.push_back(Event::Enter(state.clone()));
+    }
+
+    loop {
+        let event = match queue.pop_front() {
+            Some(event) => event,
+            None => {
+                let deadline = match (event_deadline, state_deadline) {
+                    (Some(e), Some(s)) => Some(if reached(e, s) { s } else { e }),
+                    (e, s) => e.or(s),
+                };
+                // Only polls if a deadline already passed, a message that
+                // is already queued goes first
+                match next_message::<T>(deadline) {
+                    Some(Next::Event(event)) => event,
+                    Some(Next::Stop(reason)) => {
+                        T::terminate(reason, state, data);
+                        return reason;
+                    }
+                    None => {
+                        let now = thread::get_ticks();
+                        if state_deadline.map_or(false, |d| reached(d, now)) {
+                            state_deadline = None;
+                            Event::StateTimeout
+                        } else if event_deadline.map_or(false, |d| reached(d, now)) {
+                            event_deadline = None;
+                            Event::EventTimeout
+                        } else {
+                            // Woken before the deadline, wait again
+                            continue;
+                        }
+                    }
+                }
+            }
+        };
+
+        if !matches!(event, Event::Enter(_)) {
+            event_deadline = None;
+        }
+        let can_postpone = matches!(event, Event::Call(..) | Event::Cast(_) | Event::Info(..));
+        if can_postpone && T::postpone(&event, &state, &data) {
+            postponed.push_back(event);
+            continue;
+        }
+
+        let transition = T::handle_event(event, &state, data);
+        data = transition.data;
+        match transition.action {
+            Action::Next(next) if next != state => {
+                let prev = core::mem::replace(&mut state, next);
+                state_deadline = None;
+                // Postponed events go first, in the order they came in
+                while let Some(event) = postponed.pop_back() {
+                    queue.push_front(event);
+                }
+                if T::STATE_ENTER {
+                    queue.push_front(Event::Enter(prev));
+                }
+            }
+            Action::Next(_) | Action::Keep => (),
+            Action::Stop(reason) => {
+                T::terminate(reason, state, data);
+                return reason;
+            }
+        }
+
+        let now = thread::get_ticks();
+        if let Some(ticks) = transition.event_timeout {
+            event_deadline = Some(now.wrapping_add(ticks));
+        }
+        if let Some(ticks) = transition.state_timeout {
+            state_deadline = Some(now.wrapping_add(ticks));
+        }
+    }
+}
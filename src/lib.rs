@@ -10,45 +10,70 @@
 //!
 //! # Safety
 //! Horrendous.
+//!
+//! # Ports
+//! Alkyn runs on the RP2040 by default. Building with
+//! `--no-default-features --features hosted` swaps in a simulation on a
+//! std host instead, see [`port::hosted`].
 
-#![no_std]
+#![cfg_attr(not(feature = "hosted"), no_std)]
 #![feature(core_intrinsics)]
 #![feature(asm_const)]
 #![feature(const_option)]
 #![feature(generic_const_exprs)]
+#![feature(never_type)]
+#![feature(associated_type_defaults)]
 #![feature(default_alloc_error_handler)]
 #![allow(non_upper_case_globals)]
 #![feature(const_btree_new)]
 #![feature(ptr_to_from_bits)]
 
+#[cfg(not(feature = "hosted"))]
 pub use cortex_m_rt as rt;
 pub use defmt;
 
 use defmt::info;
+#[cfg(not(feature = "hosted"))]
 use hal::pac;
+#[cfg(not(feature = "hosted"))]
 use panic_probe as _;
+#[cfg(not(feature = "hosted"))]
 use rp2040_hal as hal;
 
+pub mod agent;
+pub mod genevent;
 pub mod genserver;
+pub mod genstatem;
 pub mod heap;
+#[cfg(not(feature = "hosted"))]
 pub mod logger;
 pub(crate) mod multi;
+pub mod port;
 pub mod processor;
-pub(crate) mod supervisor;
+pub mod supervisor;
 pub mod sync;
+pub mod sys;
+pub mod task;
 pub mod thread;
 
 // Setup allocator
+#[cfg(not(feature = "hosted"))]
 use core::mem::MaybeUninit;
+#[cfg(not(feature = "hosted"))]
 use heap::AlkynHeap;
+#[cfg(not(feature = "hosted"))]
 const HEAP_SIZE: usize = 64000; // 64kb
 
+// The hosted port uses the system allocator
+#[cfg(not(feature = "hosted"))]
 #[global_allocator]
 static mut ALLOCATOR: AlkynHeap = AlkynHeap::empty();
 
+#[cfg(not(feature = "hosted"))]
 static mut TIMER: Option<hal::Timer> = Option::None;
 
 // Setup logging
+#[cfg(not(feature = "hosted"))]
 defmt::timestamp!("{=u8}:{=u32:us}", { processor::get_current_core() }, {
     // safety, this is read only
     unsafe {
@@ -72,17 +97,11 @@ defmt::timestamp!("{=u8}:{=u32:us}", { processor::get_current_core() }, {
 /// let mut m_pac = cortex_m::Peripherals::take().unwrap();
 /// alkyn::init(pac.TIMER, &mut pac.RESETS);
 /// ```
+#[cfg(not(feature = "hosted"))]
 pub fn init(timer: pac::TIMER, resets: &mut pac::RESETS) {
     info!("alkyn: Initing memory and peripherals");
     // Fix spinlocks
-    unsafe {
-        const SIO_BASE: u32 = 0xd0000000;
-        const SPINLOCK0_PTR: *mut u32 = (SIO_BASE + 0x100) as *mut u32;
-        const SPINLOCK_COUNT: usize = 32;
-        for i in 0..SPINLOCK_COUNT {
-            SPINLOCK0_PTR.wrapping_add(i).write_volatile(1);
-        }
-    }
+    unsafe { port::reset_spinlocks() };
     info!("alkyn: Initing memory and peripherals");
 
     static mut HEAP: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
@@ -93,12 +112,22 @@ pub fn init(timer: pac::TIMER, resets: &mut pac::RESETS) {
     info!("alkyn: Heap initialized!");
 }
 
+/// Initialize the kernel on the hosted port.
+///
+/// There are no peripherals to hand over and the system allocator is used,
+/// so this only resets the simulated spinlocks.
+#[cfg(feature = "hosted")]
+pub fn init() {
+    info!("alkyn: Initing hosted port");
+    unsafe { port::reset_spinlocks() };
+}
+
 /// Starts the Kernel and associated threads.
 /// 
 /// Should be called last.
 /// # Important
 /// DOES NOT RETURN
-pub fn start(systick: &mut cortex_m::peripheral::SYST, ticks: u32) -> ! {
+pub fn start(systick: &mut port::Systick, ticks: u32) -> ! {
     info!("alkyn: Starting");
     thread::init(systick, ticks)
 }
@@ -1,15 +1,9 @@
-//! Multicore support 
+//! Multicore support
+//!
+//! Cores talk to each other with `u32` messages, the port decides how
+//! they are carried (the SIO FIFO on the RP2040).
 
-use core::sync::atomic;
-use defmt::info;
-use hal::pac::{interrupt, Interrupt, NVIC};
-use hal::{pac, Sio};
-use rp2040_hal as hal;
-
-mod init;
-use init::Stack;
-
-use crate::thread;
+use crate::{port, thread};
 
 #[repr(u32)]
 #[derive(Copy, Clone)]
@@ -17,58 +11,26 @@ enum MessageType {
     PendSv,
 }
 
-// const NVIC_ICER: u32 = 0xe180;
-
-static mut CORE1_STACK: Stack<4096> = Stack::new();
-static mut CORE1_INIT: atomic::AtomicBool = atomic::AtomicBool::new(false);
-
 pub fn init_cores() {
-    // Initialize message heap
-
-    // Safety: We only use the required fields in this mod
-    let mut pac = unsafe { pac::Peripherals::steal() };
-    let mut sio = Sio::new(pac.SIO);
-    let mut mc = init::Multicore::new(&mut pac.PSM, &mut pac.PPB, &mut sio);
-
-    let cores = mc.cores();
-    let core1 = &mut cores[1];
-    let _ = core1.spawn(core_boot, unsafe { &mut CORE1_STACK.mem });
-    unsafe { CORE1_INIT.store(true, atomic::Ordering::Release) }
-}
-
-// Boot scheduler on each core
-fn core_boot() -> ! {
-    info!("Core 1 online");
-    unsafe {
-        NVIC::unmask(Interrupt::SIO_IRQ_PROC1);
-    }
-
-    loop {}
+    port::boot_cores();
 }
 
 /// Set PendSV on Core1
 ///
 /// Only call within critical section
 pub unsafe fn send_pendsv() {
-    let pac = pac::Peripherals::steal();
-    let mut sio = Sio::new(pac.SIO);
-    sio.fifo.drain();
-    sio.fifo.write_blocking(MessageType::PendSv as u32)
+    port::send_core_message(MessageType::PendSv as u32)
 }
 
-#[interrupt]
-fn SIO_IRQ_PROC1() {
-    let pac = unsafe { pac::Peripherals::steal() };
-    let mut sio = Sio::new(pac.SIO);
-
+/// Handle a message sent from the other core.
+///
+/// Called by the port from the receiving core's interrupt.
+pub fn handle_message(raw: u32) {
     // Safety: We know u32 is the enum type
-    let msg: MessageType = unsafe { core::mem::transmute(sio.fifo.read_blocking()) };
-    sio.fifo.drain();
+    let msg: MessageType = unsafe { core::mem::transmute(raw) };
     match msg {
         MessageType::PendSv => {
             thread::systick::run_ctxswitch();
         }
     }
-
-    sio.fifo.write(1);
 }
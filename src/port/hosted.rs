@@ -0,0 +1,357 @@
+//! Hosted port.
+//!
+//! Runs the kernel as a simulation on a std host, so scheduling and
+//! messaging can be tested with `cargo test-hosted` instead of a board.
+//!
+//! Every kernel thread gets its own OS thread, but only a thread the
+//! scheduler has made current on one of the two simulated cores is allowed
+//! to run. A context switch hands the core to the next thread and parks
+//! the old one until it is switched back in.
+//!
+//! Interrupts are simulated as well: the tick comes from a timer thread
+//! and core messages are queued per core. Both are only delivered to the
+//! thread running on that core when it enters the kernel, i.e. leaves a
+//! critical section, re-enables interrupts or waits for an event. A thread
+//! spinning without calling into the kernel is therefore never preempted.
+//!
+//! # Example
+//! ```no_run
+//! use alkyn::port::Systick;
+//!
+//! alkyn::init();
+//! alkyn::thread::create_thread("task1", Box::leak(Box::new([0; 128])), || loop {
+//!     alkyn::thread::sleep(10);
+//! }).unwrap();
+//! std::thread::spawn(|| alkyn::start(&mut Systick, 80_000));
+//! ```
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+use std::vec::Vec;
+
+use crate::multi;
+
+const CORES: usize = 2;
+const SPINLOCK_COUNT: usize = 32;
+
+/// Clock the tick reload value is counted in, the RP2040's default.
+const CLOCK_HZ: u64 = 125_000_000;
+
+const PENDING_TICK: u8 = 0b001;
+const PENDING_MESSAGE: u8 = 0b010;
+const PENDING_PENDSV: u8 = 0b100;
+
+/// Stand-in for the SysTick peripheral, the tick is driven by a host timer.
+pub struct Systick;
+
+const UNLOCKED: AtomicBool = AtomicBool::new(false);
+static SPINLOCKS: [AtomicBool; SPINLOCK_COUNT] = [UNLOCKED; SPINLOCK_COUNT];
+
+/// Interrupts waiting to be taken, per core.
+struct Events {
+    pending: [u8; CORES],
+    messages: [Vec<u32>; CORES],
+}
+
+static EVENTS: Mutex<Events> = Mutex::new(Events {
+    pending: [0; CORES],
+    messages: [Vec::new(), Vec::new()],
+});
+static EVENT_RAISED: Condvar = Condvar::new();
+
+/// The OS thread inside the critical section, if any.
+static CS_OWNER: Mutex<Option<ThreadId>> = Mutex::new(None);
+static CS_FREE: Condvar = Condvar::new();
+
+/// Contexts by the stack pointer `init_stack` handed out for them.
+static CONTEXTS: Mutex<BTreeMap<usize, Arc<Context>>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    /// Simulated core this OS thread is running on.
+    static CORE: Cell<u8> = Cell::new(0);
+    /// Kernel thread context this OS thread belongs to, `None` for boot.
+    static CONTEXT: RefCell<Option<Arc<Context>>> = RefCell::new(None);
+    static IRQ_MASKED: Cell<bool> = Cell::new(false);
+    static IN_HANDLER: Cell<bool> = Cell::new(false);
+}
+
+/// A kernel thread, backed by an OS thread once it is first switched in.
+struct Context {
+    entry: fn(usize) -> !,
+    arg: usize,
+    started: AtomicBool,
+    /// Set to the core to run on when switched in
+    resume_on: Mutex<Option<u8>>,
+    resumed: Condvar,
+}
+
+impl Context {
+    fn new(entry: fn(usize) -> !, arg: usize) -> Context {
+        Context {
+            entry,
+            arg,
+            started: AtomicBool::new(false),
+            resume_on: Mutex::new(None),
+            resumed: Condvar::new(),
+        }
+    }
+
+    fn resume(self: &Arc<Self>, core: u8) {
+        *self.resume_on.lock().unwrap() = Some(core);
+        self.resumed.notify_one();
+
+        if !self.started.swap(true, Ordering::AcqRel) {
+            let ctx = self.clone();
+            thread::Builder::new()
+                .name("alkyn thread".into())
+                .spawn(move || {
+                    CONTEXT.with(|c| *c.borrow_mut() = Some(ctx.clone()));
+                    ctx.park();
+                    let (entry, arg) = (ctx.entry, ctx.arg);
+                    // A dead kernel thread would hold its core forever,
+                    // take the whole simulation down instead.
+                    if panic::catch_unwind(AssertUnwindSafe(|| entry(arg))).is_err() {
+                        std::process::exit(101);
+                    }
+                })
+                .expect("alkyn: could not spawn host thread");
+        }
+    }
+
+    /// Block until switched back in.
+    fn park(&self) {
+        let mut resume_on = self.resume_on.lock().unwrap();
+        loop {
+            if let Some(core) = resume_on.take() {
+                CORE.with(|c| c.set(core));
+                return;
+            }
+            resume_on = self.resumed.wait(resume_on).unwrap();
+        }
+    }
+}
+
+struct HostCriticalSection;
+critical_section::custom_impl!(HostCriticalSection);
+
+unsafe impl critical_section::Impl for HostCriticalSection {
+    unsafe fn acquire() -> u8 {
+        let me = thread::current().id();
+        let mut owner = CS_OWNER.lock().unwrap();
+        if *owner == Some(me) {
+            return 0;
+        }
+        while owner.is_some() {
+            owner = CS_FREE.wait(owner).unwrap();
+        }
+        *owner = Some(me);
+        1
+    }
+
+    unsafe fn release(token: u8) {
+        if token == 0 {
+            return;
+        }
+        {
+            let mut owner = CS_OWNER.lock().unwrap();
+            assert!(
+                *owner == Some(thread::current().id()),
+                "critical section released by a thread not holding it"
+            );
+            *owner = None;
+        }
+        CS_FREE.notify_all();
+        service_pending();
+    }
+}
+
+fn in_critical_section() -> bool {
+    *CS_OWNER.lock().unwrap() == Some(thread::current().id())
+}
+
+fn raise(core: usize, pending: u8) {
+    EVENTS.lock().unwrap().pending[core] |= pending;
+    EVENT_RAISED.notify_all();
+}
+
+/// Take every pending interrupt on this core, like the NVIC would as soon
+/// as they are unmasked.
+fn service_pending() {
+    if IRQ_MASKED.with(Cell::get) || IN_HANDLER.with(Cell::get) || in_critical_section() {
+        return;
+    }
+
+    IN_HANDLER.with(|h| h.set(true));
+    loop {
+        let core = get_current_core() as usize;
+        let (pending, messages) = {
+            let mut events = EVENTS.lock().unwrap();
+            (
+                core::mem::take(&mut events.pending[core]),
+                core::mem::take(&mut events.messages[core]),
+            )
+        };
+        if pending == 0 {
+            break;
+        }
+
+        if pending & PENDING_TICK != 0 {
+            crate::thread::systick::on_tick();
+        }
+        for msg in messages {
+            multi::handle_message(msg);
+        }
+        if pending & PENDING_PENDSV != 0 {
+            pendsv();
+        }
+    }
+    IN_HANDLER.with(|h| h.set(false));
+}
+
+/// Switch this core to the thread the scheduler picked.
+fn pendsv() {
+    let next = crate::thread::get_next_thread_ptr();
+    unsafe { crate::thread::ALKYN_THREADS_GLOBAL.set_next_to_curr() };
+
+    // Safety: next points at a TCB, which starts with its stack pointer
+    let sp = unsafe { *(next as *const usize) };
+    let next = CONTEXTS
+        .lock()
+        .unwrap()
+        .get(&sp)
+        .cloned()
+        .expect("alkyn: no context for thread");
+
+    let current = CONTEXT.with(|c| c.borrow().clone());
+    if let Some(current) = &current {
+        if Arc::ptr_eq(current, &next) {
+            return;
+        }
+    }
+
+    next.resume(get_current_core());
+    match current {
+        Some(current) => current.park(),
+        // Nothing ever switches back to a core's boot context
+        None => loop {
+            thread::park();
+        },
+    }
+}
+
+#[inline]
+pub fn wait_for_interrupt() {
+    wait_for_event();
+}
+
+pub fn wait_for_event() {
+    let core = get_current_core() as usize;
+    {
+        let mut events = EVENTS.lock().unwrap();
+        while events.pending[core] == 0 {
+            events = EVENT_RAISED.wait(events).unwrap();
+        }
+    }
+    service_pending();
+}
+
+#[inline]
+pub unsafe fn enable_interrupts() {
+    IRQ_MASKED.with(|m| m.set(false));
+    service_pending();
+}
+
+#[inline]
+pub unsafe fn disable_interrupts() {
+    IRQ_MASKED.with(|m| m.set(true));
+}
+
+#[inline]
+pub fn get_current_core() -> u8 {
+    CORE.with(Cell::get)
+}
+
+/// Whether a simulated interrupt handler is running on this thread.
+#[inline]
+pub fn in_interrupt() -> bool {
+    IN_HANDLER.with(Cell::get)
+}
+
+pub unsafe fn set_pendsv() {
+    raise(get_current_core().into(), PENDING_PENDSV);
+    service_pending();
+}
+
+pub unsafe fn reset_spinlocks() {
+    for lock in SPINLOCKS.iter() {
+        lock.store(false, Ordering::Release);
+    }
+}
+
+#[inline]
+pub fn spinlock_try_claim(lock: usize) -> bool {
+    SPINLOCKS[lock]
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_ok()
+}
+
+#[inline]
+pub unsafe fn spinlock_release(lock: usize) {
+    SPINLOCKS[lock].store(false, Ordering::Release);
+}
+
+/// Register a context calling `entry` with `arg`.
+///
+/// The stack itself is unused, the OS thread has its own. The returned
+/// stack pointer only identifies the context, re-framing the same stack
+/// replaces it.
+pub fn init_stack(stack: &mut [u32], entry: fn(usize) -> !, arg: usize) -> usize {
+    let sp = stack.as_ptr_range().end as usize;
+    CONTEXTS
+        .lock()
+        .unwrap()
+        .insert(sp, Arc::new(Context::new(entry, arg)));
+    sp
+}
+
+/// Start the tick timer, `reload` is counted in 125MHz cycles.
+pub fn enable_tick(_syst: &mut Systick, reload: u32) {
+    let period = Duration::from_nanos(reload as u64 * 1_000_000_000 / CLOCK_HZ);
+    thread::Builder::new()
+        .name("alkyn systick".into())
+        .spawn(move || loop {
+            thread::sleep(period);
+            raise(0, PENDING_TICK);
+        })
+        .expect("alkyn: could not spawn tick thread");
+}
+
+/// There is no cycle counter to read on the host.
+#[inline]
+pub fn tick_current() -> u32 {
+    0
+}
+
+/// Start core 1, which waits for its first context switch.
+pub fn boot_cores() {
+    thread::Builder::new()
+        .name("alkyn core1".into())
+        .spawn(|| {
+            CORE.with(|c| c.set(1));
+            loop {
+                wait_for_event();
+            }
+        })
+        .expect("alkyn: could not spawn core 1");
+}
+
+/// Send a message to the other core.
+pub unsafe fn send_core_message(msg: u32) {
+    let other = (get_current_core() as usize + 1) % CORES;
+    EVENTS.lock().unwrap().messages[other].push(msg);
+    raise(other, PENDING_MESSAGE);
+}
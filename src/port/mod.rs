@@ -0,0 +1,36 @@
+//! Hardware back-ends for the kernel.
+//!
+//! Everything that touches a CPU register, an exception or a peripheral
+//! lives behind this module, so `thread`, `multi` and `sync` only ever talk
+//! to the functions re-exported here. Exactly one port is compiled in:
+//!
+//! * `rp2040` (default): the real thing, Cortex-M0+ exceptions and SIO.
+//! * `hosted`: a simulation on a std host, so the kernel can be run and
+//!   tested on a desktop. See [`hosted`] for how it differs.
+//!
+//! Every port provides:
+//!
+//! * CPU helpers: `wait_for_interrupt`, `wait_for_event`,
+//!   `enable_interrupts`, `disable_interrupts`, `get_current_core`,
+//!   `in_interrupt` and `set_pendsv`.
+//! * Spinlocks: `reset_spinlocks`, `spinlock_try_claim` and
+//!   `spinlock_release`.
+//! * Contexts: `init_stack`, framing a stack so the first context switch
+//!   into it calls the thread's entry point with a word-sized argument.
+//! * The tick: a `Systick` type, `enable_tick` and `tick_current`. The
+//!   port calls [`crate::thread::systick::on_tick`] on every tick.
+//! * Multicore: `boot_cores` and `send_core_message`. Messages are handed
+//!   to [`crate::multi::handle_message`] on the receiving core.
+
+#[cfg(all(not(feature = "hosted"), not(feature = "rp2040")))]
+compile_error!("alkyn: enable either the `rp2040` or the `hosted` feature");
+
+#[cfg(not(feature = "hosted"))]
+mod rp2040;
+#[cfg(not(feature = "hosted"))]
+pub use self::rp2040::*;
+
+#[cfg(feature = "hosted")]
+pub mod hosted;
+#[cfg(feature = "hosted")]
+pub use self::hosted::*;
@@ -0,0 +1,224 @@
+//! RP2040 port.
+//!
+//! Context switching is done in `PendSV`, the tick comes from `SysTick` on
+//! core 0 and is forwarded to core 1 over the SIO FIFO.
+use core::arch::asm;
+use core::ptr;
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::{asm, interrupt, register};
+use cortex_m_rt::exception;
+use defmt::info;
+use hal::pac::{interrupt, Interrupt, NVIC};
+use hal::Sio;
+use rp2040_hal as hal;
+
+use crate::{multi, pac, thread};
+
+mod multicore;
+use multicore::Stack;
+
+const ICSR: u32 = 0xE000ED04;
+
+const SIO_BASE: u32 = 0xd0000000;
+const SPINLOCK0_PTR: *mut u32 = (SIO_BASE + 0x100) as *mut u32;
+const SPINLOCK_COUNT: usize = 32;
+
+/// The SysTick peripheral drives the kernel tick.
+pub type Systick = cortex_m::peripheral::SYST;
+
+#[inline]
+pub fn wait_for_interrupt() {
+    asm::wfi();
+}
+
+#[inline]
+pub fn wait_for_event() {
+    asm::wfe();
+}
+
+#[inline]
+pub unsafe fn enable_interrupts() {
+    interrupt::enable();
+}
+
+#[inline]
+pub unsafe fn disable_interrupts() {
+    interrupt::disable();
+}
+
+#[inline]
+pub fn get_current_core() -> u8 {
+    // Safety: Always safe to read read-only register
+    unsafe { (*pac::SIO::ptr()).cpuid.read().bits() as u8 }
+}
+
+/// Whether an exception handler is running, read from `ICSR.VECTACTIVE`.
+#[inline]
+pub fn in_interrupt() -> bool {
+    // Safety: Always safe to read
+    unsafe { ptr::read_volatile(ICSR as *const u32) & 0x1FF != 0 }
+}
+
+#[inline]
+pub unsafe fn set_pendsv() {
+    let pend = ptr::read_volatile(ICSR as *const u32);
+    ptr::write_volatile(ICSR as *mut u32, pend | 1 << 28);
+}
+
+/// Release every hardware spinlock, they survive a soft reset.
+pub unsafe fn reset_spinlocks() {
+    for i in 0..SPINLOCK_COUNT {
+        SPINLOCK0_PTR.wrapping_add(i).write_volatile(1);
+    }
+}
+
+#[inline]
+pub fn spinlock_try_claim(lock: usize) -> bool {
+    let sio = unsafe { &*pac::SIO::ptr() };
+    sio.spinlock[lock].read().bits() > 0
+}
+
+#[inline]
+pub unsafe fn spinlock_release(lock: usize) {
+    let sio = &*pac::SIO::ptr();
+    sio.spinlock[lock].write_with_zero(|b| b.bits(1))
+}
+
+/// Frame `stack` as if `entry` had been interrupted right as it was called
+/// with `arg`, returning the stack pointer `PendSV` should restore.
+pub fn init_stack(stack: &mut [u32], entry: fn(usize) -> !, arg: usize) -> usize {
+    let idx = stack.len() - 1;
+
+    let pc: usize = (entry as *const fn()).to_bits();
+
+    // Init registers
+    stack[idx] = 1 << 24; // xPSR
+    stack[idx - 1] = pc as u32;
+
+    // Fill with dummy vals
+    stack[idx - 2] = 0xFFFFFFFD; // return reg
+    stack[idx - 3] = 0xCCCCCCCC; // R12
+    stack[idx - 4] = 0x33333333; // R3
+    stack[idx - 5] = 0x22222222; // R2
+    stack[idx - 6] = 0x11111111; // R1
+    stack[idx - 7] = arg as u32; // R0, first argument
+    stack[idx - 08] = 0x77777777; // R7
+    stack[idx - 09] = 0x66666666; // R6
+    stack[idx - 10] = 0x55555555; // R5
+    stack[idx - 11] = 0x44444444; // R4
+    stack[idx - 12] = 0xBBBBBBBB; // R11
+    stack[idx - 13] = 0xAAAAAAAA; // R10
+    stack[idx - 14] = 0x99999999; // R9
+    stack[idx - 15] = 0x88888888; // R8
+
+    unsafe { core::intrinsics::transmute(&stack[stack.len() - 16]) }
+}
+
+pub fn enable_tick(syst: &mut Systick, reload: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(reload);
+    syst.clear_current();
+    syst.enable_counter();
+    syst.enable_interrupt();
+}
+
+#[inline]
+pub fn tick_current() -> u32 {
+    Systick::get_current()
+}
+
+#[exception]
+fn SysTick() {
+    thread::systick::on_tick();
+}
+
+#[exception]
+fn PendSV() {
+    unsafe {
+        disable_interrupts(); // We enable later on in asm
+        let current_thread = thread::get_current_thread_ptr();
+        let mut psp = register::psp::read();
+        defmt::trace!("curr thr: {:#x}", current_thread);
+        if current_thread > 1 {
+            psp = psp - 16;
+            asm!(
+                "stmia r0!, {{r4-r7}}",
+                "mov r4, r8",
+                "mov r5, r9",
+                "mov r7, r11",
+                "subs r0, #32",
+                "stmia r0!, {{r4-r7}}",
+                "subs r0, #16", // possibly need another ld here
+                "str r0, [{cur}, 0x0]",
+                cur = in(reg) current_thread,
+                in("r0") psp,
+            );
+        }
+        let next = thread::get_next_thread_ptr();
+        defmt::trace!("nxt thr: {:#x}", next);
+        let kernel = &mut thread::ALKYN_THREADS_GLOBAL;
+        kernel.set_next_to_curr();
+        defmt::trace!("switching ctx");
+        asm!(
+            "ldr r3, [{nxt}, 0x0]", // next.sp
+            "ldmia r3!, {{r4-r7}}", // Load stack
+            "mov r8, r4", // Move to higher vars
+            "mov r9,  r5",
+            "mov r10, r6",
+            "mov r11, r7",
+            "ldmia	r3!, {{r4-r7}}", // Load rest of stack
+            "msr psp, r3", // Set stack pointer
+            "ldr r0, =0xFFFFFFFD", // set execution mode
+            "cpsie i", // Enable interrupts here
+            "bx r0",
+            nxt = in(reg) next,
+            options(noreturn)
+        );
+    }
+}
+
+static mut CORE1_STACK: Stack<4096> = Stack::new();
+
+/// Start core 1, which waits for its first context switch.
+pub fn boot_cores() {
+    // Safety: We only use the required fields in this mod
+    let mut pac = unsafe { pac::Peripherals::steal() };
+    let mut sio = Sio::new(pac.SIO);
+    let mut mc = multicore::Multicore::new(&mut pac.PSM, &mut pac.PPB, &mut sio);
+
+    let cores = mc.cores();
+    let core1 = &mut cores[1];
+    let _ = core1.spawn(core_boot, unsafe { &mut CORE1_STACK.mem });
+}
+
+// Boot scheduler on each core
+fn core_boot() -> ! {
+    info!("Core 1 online");
+    unsafe {
+        NVIC::unmask(Interrupt::SIO_IRQ_PROC1);
+    }
+
+    loop {}
+}
+
+/// Send a message to the other core over the SIO FIFO.
+///
+/// Only call within critical section
+pub unsafe fn send_core_message(msg: u32) {
+    let pac = pac::Peripherals::steal();
+    let mut sio = Sio::new(pac.SIO);
+    sio.fifo.drain();
+    sio.fifo.write_blocking(msg)
+}
+
+#[interrupt]
+fn SIO_IRQ_PROC1() {
+    let pac = unsafe { pac::Peripherals::steal() };
+    let mut sio = Sio::new(pac.SIO);
+
+    let msg = sio.fifo.read_blocking();
+    sio.fifo.drain();
+    multi::handle_message(msg);
+
+    sio.fifo.write(1);
+}
@@ -1,45 +1,45 @@
 //! Access to useful CPU functions.
-//! 
-//! Mostly just wrappers around pre-existing ASM instructions.
-use crate::pac;
-use core::ptr;
-use cortex_m::{asm, interrupt};
-
-const ICSR: u32 = 0xE000ED04;
+//!
+//! Mostly just wrappers around the current [`port`](crate::port).
+use crate::port;
 
 /// Nice CPU helper functions
 
 /// Hint to the CPU to wait for the next interrupt
 #[inline]
 pub fn wait_for_interrrupt() {
-    asm::wfi();
+    port::wait_for_interrupt();
 }
 
 // Hint for the CPU top wait for the next event
 #[inline]
 pub fn wait_for_event() {
-    asm::wfe();
+    port::wait_for_event();
 }
 
 #[inline]
 pub unsafe fn enable_interrupts() {
-    interrupt::enable();
+    port::enable_interrupts();
 }
 
 #[inline]
 pub unsafe fn disable_interrupts() {
-    interrupt::disable();
+    port::disable_interrupts();
 }
 
 // Get the current core we're executing on.
 #[inline]
 pub fn get_current_core() -> u8 {
-    // Safety: Always safe to read read-only register
-    unsafe { (*pac::SIO::ptr()).cpuid.read().bits() as u8 }
+    port::get_current_core()
+}
+
+// Whether we're inside an interrupt or exception handler.
+#[inline]
+pub fn in_interrupt() -> bool {
+    port::in_interrupt()
 }
 
 #[inline]
 pub unsafe fn set_pendsv() {
-    let pend = ptr::read_volatile(ICSR as *const u32);
-    ptr::write_volatile(ICSR as *mut u32, pend | 1 << 28);
+    port::set_pendsv();
 }
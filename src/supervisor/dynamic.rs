@@ -0,0 +1,133 @@
+//! Dynamic supervisors
+//!
+//! A [`DynamicSupervisor`] starts with no children. They are added while
+//! it runs with [`DynamicSupervisor::start_child`] and removed with
+//! [`DynamicSupervisor::terminate_child`], e.g. one worker per device
+//! that shows up on a bus.
+//!
+//! Every child is restarted on its own, as with `Strategy::OneForOne`,
+//! under the same restart intensity as a [`Supervisor`](super::Supervisor).
+//! Children that are not restarted are removed.
+extern crate alloc;
+use alloc::vec::Vec;
+use defmt::Format;
+
+use super::{note_exit, restart, shutdown, spawn, Child, ChildSpec, Intensity};
+use crate::genserver::{self, From};
+use crate::thread::{self, msg, Exit, ExitReason, MonitorRef, Pid};
+
+/// Why a dynamic supervisor could not start or stop a child
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum ChildError {
+    /// The supervisor already runs `max_children` children
+    MaxChildren,
+    /// Creating the child's thread failed with this error
+    Create(u8),
+    /// The pid is not a child of the supervisor
+    NotFound,
+    /// The supervisor is not alive
+    NoSupervisor,
+}
+
+pub struct DynamicSupervisor {
+    max_children: usize,
+    intensity: Intensity,
+    children: Vec<Child>,
+}
+
+/// Requests to a dynamic supervisor, the tag comes back with the reply
+enum Request {
+    StartChild(MonitorRef, ChildSpec),
+    TerminateChild(MonitorRef, Pid),
+}
+
+impl DynamicSupervisor {
+    pub fn new(max_restarts: u32, max_ticks: u32, max_children: usize) -> DynamicSupervisor {
+        DynamicSupervisor {
+            max_children,
+            intensity: Intensity::new(max_restarts, max_ticks),
+            children: Vec::new(),
+        }
+    }
+
+    /// Start the supervisor process, without any children yet.
+    pub fn start(self, name: &'static str, stack: &'static mut [u32]) -> Result<Pid, u8> {
+        spawn(self, name, stack, Self::run)
+    }
+
+    /// Ask the supervisor `sup` to start a child from `spec`.
+    pub fn start_child(sup: Pid, spec: ChildSpec) -> Result<Pid, ChildError> {
+        request(sup, |tag| Request::StartChild(tag, spec))
+    }
+
+    /// Ask the supervisor `sup` to stop its child `pid` and forget it.
+    pub fn terminate_child(sup: Pid, pid: Pid) -> Result<(), ChildError> {
+        request(sup, |tag| Request::TerminateChild(tag, pid))
+    }
+
+    fn run() -> ! {
+        thread::trap_exit(true);
+        let mut sup = *msg::receive_of::<DynamicSupervisor>();
+
+        loop {
+            let (from, m) = msg::receive_with_sender();
+            let m = match m.downcast::<Exit>() {
+                Ok(exit) => {
+                    sup.child_exited(exit.pid, exit.reason);
+                    continue;
+                }
+                Err(m) => m,
+            };
+            match m.downcast::<Request>().map(|r| *r) {
+                Ok(Request::StartChild(tag, spec)) => {
+                    genserver::reply(From::new(from, tag), sup.start_child_from(spec));
+                }
+                Ok(Request::TerminateChild(tag, pid)) => {
+                    genserver::reply(From::new(from, tag), sup.terminate_child_by(pid));
+                }
+                Err(_) => defmt::warn!("alkyn: dynamic supervisor dropped unknown msg"),
+            }
+        }
+    }
+
+    fn start_child_from(&mut self, spec: ChildSpec) -> Result<Pid, ChildError> {
+        if self.children.len() >= self.max_children {
+            return Err(ChildError::MaxChildren);
+        }
+        let mut child = Child::new(spec);
+        let pid = child.start().map_err(ChildError::Create)?;
+        self.children.push(child);
+        Ok(pid)
+    }
+
+    fn terminate_child_by(&mut self, pid: Pid) -> Result<(), ChildError> {
+        let i = self
+            .children
+            .iter()
+            .position(|c| c.pid == Some(pid))
+            .ok_or(ChildError::NotFound)?;
+        self.children[i].stop();
+        self.children.remove(i);
+        Ok(())
+    }
+
+    fn child_exited(&mut self, pid: Pid, reason: ExitReason) {
+        let i = match self.children.iter().position(|c| c.pid == Some(pid)) {
+            Some(i) => i,
+            // Something else we are linked to went down
+            None if reason != ExitReason::Normal => shutdown(&mut self.children, reason),
+            None => return,
+        };
+
+        if note_exit(&mut self.children, &mut self.intensity, i, reason) {
+            restart(&mut self.children, i);
+        } else {
+            self.children.remove(i);
+        }
+    }
+}
+
+/// Send a request to `sup` and wait for its reply, or for it to go down.
+fn request<T: 'static>(sup: Pid, make: impl FnOnce(MonitorRef) -> Request) -> Result<T, ChildError> {
+    genserver::call_with(sup, make, None).unwrap_or(Err(ChildError::NoSupervisor))
+}
@@ -1,51 +1,350 @@
-use core::arch::asm;
-use cortex_m::register;
-use cortex_m_rt::exception;
-
-use crate::{thread, processor};
-
-
-#[exception]
-fn PendSV() {
-    unsafe {
-        processor::disable_interrupts(); // We enable later on in asm
-        let current_thread = thread::get_current_thread_ptr();
-        let mut psp = register::psp::read();
-        defmt::trace!("curr thr: {:#x}", current_thread);
-        if current_thread > 1 {
-            psp = psp - 16;
-            asm!(
-                "stmia r0!, {{r4-r7}}",
-                "mov r4, r8",
-                "mov r5, r9",
-                "mov r7, r11",
-                "subs r0, #32",
-                "stmia r0!, {{r4-r7}}",
-                "subs r0, #16", // possibly need another ld here
-                "str r0, [{cur}, 0x0]",
-                cur = in(reg) current_thread,
-                in("r0") psp,
-            );
-        }
-        let next = thread::get_next_thread_ptr();
-        defmt::trace!("nxt thr: {:#x}", next);
-        let kernel = &mut thread::ALKYN_THREADS_GLOBAL;
-        kernel.set_next_to_curr();
-        defmt::trace!("switching ctx");
-        asm!(
-            "ldr r3, [{nxt}, 0x0]", // next.sp
-            "ldmia r3!, {{r4-r7}}", // Load stack
-            "mov r8, r4", // Move to higher vars
-            "mov r9,  r5",
-            "mov r10, r6",
-            "mov r11, r7",
-            "ldmia	r3!, {{r4-r7}}", // Load rest of stack
-            "msr psp, r3", // Set stack pointer
-            "ldr r0, =0xFFFFFFFD", // set execution mode
-            "cpsie i", // Enable interrupts here
-            "bx r0",
-            nxt = in(reg) next,
-            options(noreturn)
-        );
-    }
-}
\ No newline at end of file
+//! # Erlang-like Supervisors.
+//!
+//! A supervisor is a process that starts a list of children, links to
+//! them and restarts them when they terminate. Which children are
+//! restarted along with the one that terminated is decided by its
+//! [`Strategy`], whether a child is restarted at all by its [`Restart`].
+//!
+//! If children keep crashing, restarting them is pointless. A supervisor
+//! allows at most `max_restarts` restarts within `max_ticks` kernel
+//! ticks; one more and it stops all its children and terminates itself
+//! with `ExitReason::Shutdown`, leaving the problem to whatever is linked
+//! to it.
+//!
+//! A supervisor runs privileged so it can create its children, so it has
+//! to be started by a privileged process.
+//!
+//! # Example
+//! ```ignore
+//! let sup = Supervisor::new(Strategy::OneForOne, 3, 1000, vec![
+//!     ChildSpec::new("sensor", sensor_loop, unsafe { &mut SENSOR_STACK }),
+//!     ChildSpec::new("logger", logger_loop, unsafe { &mut LOGGER_STACK }),
+//! ]);
+//! sup.start("sup", unsafe { &mut SUP_STACK }).unwrap();
+//! ```
+extern crate alloc;
+use alloc::vec::Vec;
+use defmt::Format;
+
+use crate::thread::{self, msg, Core, Entry, Exit, ExitReason, IntoExitReason, Pid};
+
+mod dynamic;
+pub use dynamic::{ChildError, DynamicSupervisor};
+
+/// Which children are restarted when one terminates
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Strategy {
+    /// Only the child that terminated
+    OneForOne,
+    /// Every child
+    OneForAll,
+    /// The child that terminated and every child started after it
+    RestForOne,
+}
+
+/// When a child is restarted
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum Restart {
+    /// Always
+    Permanent,
+    /// Only if it terminated abnormally, i.e. not `Normal` or `Shutdown`
+    Transient,
+    /// Never
+    Temporary,
+}
+
+/// How to start a child
+pub struct ChildSpec {
+    pub name: &'static str,
+    entry: Entry,
+    /// Reused by every restart of the child
+    pub stack: &'static mut [u32],
+    pub priority: u8,
+    pub affinity: Core,
+    pub restart: Restart,
+}
+
+impl ChildSpec {
+    /// A permanent child with the default priority and no core affinity.
+    ///
+    /// `entry` may return, see [`IntoExitReason`].
+    pub fn new<R: IntoExitReason>(
+        name: &'static str,
+        entry: fn() -> R,
+        stack: &'static mut [u32],
+    ) -> ChildSpec {
+        ChildSpec {
+            name,
+            entry: Entry::new(entry),
+            stack,
+            priority: 0x01,
+            affinity: Core::None,
+            restart: Restart::Permanent,
+        }
+    }
+}
+
+struct Child {
+    spec: ChildSpec,
+    /// Running instance, `None` while stopped
+    pid: Option<Pid>,
+    /// Last instance, restarted in place if possible
+    last: Option<Pid>,
+}
+
+impl Child {
+    fn new(spec: ChildSpec) -> Child {
+        Child {
+            spec,
+            pid: None,
+            last: None,
+        }
+    }
+
+    /// Whether the child should be restarted after exiting with `reason`.
+    fn wants_restart(&self, reason: ExitReason) -> bool {
+        match self.spec.restart {
+            Restart::Permanent => true,
+            Restart::Transient => reason != ExitReason::Normal && reason != ExitReason::Shutdown,
+            Restart::Temporary => false,
+        }
+    }
+
+    /// Start the child and link to it.
+    ///
+    /// A restart keeps the child's `Pid`. Its slot is reserved for that
+    /// while the supervisor keeps the child, so it is only taken by another
+    /// process if the slot was released.
+    fn start(&mut self) -> Result<Pid, u8> {
+        let restarted = match self.last {
+            Some(last) => match thread::restart_thread(last) {
+                Ok(()) => Some(last),
+                Err(4) => None,
+                Err(e) => return Err(e),
+            },
+            None => None,
+        };
+        let pid = match restarted {
+            Some(pid) => pid,
+            None => {
+                // Safety: the previous instance, if any, is dead and its
+                // slot reused, so it is off every core and nothing else
+                // uses the stack
+                let stack = unsafe { &mut *(self.spec.stack as *mut [u32]) };
+                thread::create_thread_from_entry(
+                    self.spec.name,
+                    stack,
+                    self.spec.entry,
+                    self.spec.priority,
+                    false,
+                    self.spec.affinity,
+                    msg::MailboxConfig::UNBOUNDED,
+                )?
+            }
+        };
+        self.pid = Some(pid);
+        self.last = Some(pid);
+        // Only fails if it already died and its slot was reused, which the
+        // link below tells us about
+        thread::reserve_slot(pid).ok();
+        // A child that dies before this sends us a `NoProc` exit instead
+        thread::link(pid);
+        Ok(pid)
+    }
+
+    /// Terminate the running instance with `ExitReason::Shutdown`.
+    fn stop(&mut self) {
+        if let Some(pid) = self.pid.take() {
+            thread::unlink(pid);
+            thread::terminate(pid, ExitReason::Shutdown);
+            // It may have exited on its own before the unlink
+            while msg::check_receive_matching(|m| {
+                m.downcast_ref::<Exit>().map_or(false, |e| e.pid == pid)
+            })
+            .is_some()
+            {}
+        }
+    }
+
+    /// Give up the slot kept for a restart in place, for a child that is
+    /// not started again for now.
+    fn release(&mut self) {
+        if let Some(last) = self.last.take() {
+            // Fails if the slot was already reused, nothing to give up then
+            thread::release_slot(last).ok();
+        }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// At most `max_restarts` restarts within `max_ticks` kernel ticks
+struct Intensity {
+    max_restarts: u32,
+    max_ticks: u32,
+    /// Ticks at which recent restarts happened
+    restarts: Vec<u32>,
+}
+
+impl Intensity {
+    fn new(max_restarts: u32, max_ticks: u32) -> Intensity {
+        Intensity {
+            max_restarts,
+            max_ticks,
+            restarts: Vec::new(),
+        }
+    }
+
+    /// Record a restart, returns false if it exceeds the intensity.
+    fn note_restart(&mut self) -> bool {
+        let now = thread::get_ticks();
+        let max_ticks = self.max_ticks;
+        self.restarts.retain(|&t| now.wrapping_sub(t) < max_ticks);
+        if self.restarts.len() as u32 >= self.max_restarts {
+            return false;
+        }
+        self.restarts.push(now);
+        true
+    }
+}
+
+/// Note that `children[i]` exited with `reason`, returns whether it is to
+/// be restarted.
+///
+/// Shuts the calling supervisor down if the restart would exceed
+/// `intensity`.
+fn note_exit(
+    children: &mut [Child],
+    intensity: &mut Intensity,
+    i: usize,
+    reason: ExitReason,
+) -> bool {
+    children[i].pid = None;
+    if !children[i].wants_restart(reason) {
+        children[i].release();
+        return false;
+    }
+    if !intensity.note_restart() {
+        defmt::warn!("alkyn: supervisor reached its restart intensity, shutting down");
+        shutdown(children, ExitReason::Shutdown);
+    }
+    true
+}
+
+/// Start `children[i]` again, shutting the calling supervisor down if
+/// that fails.
+fn restart(children: &mut [Child], i: usize) {
+    if children[i].start().is_err() {
+        shutdown(children, ExitReason::Error("child failed to restart"));
+    }
+}
+
+/// Stop `children`, last started first, and terminate the calling
+/// supervisor.
+fn shutdown(children: &mut [Child], reason: ExitReason) -> ! {
+    for child in children.iter_mut().rev() {
+        child.stop();
+    }
+    thread::exit(reason)
+}
+
+/// Create the process of the supervisor `sup`, which `run` takes from
+/// its mailbox first.
+fn spawn<S: 'static>(
+    sup: S,
+    name: &'static str,
+    stack: &'static mut [u32],
+    run: fn() -> !,
+) -> Result<Pid, u8> {
+    let pid = thread::create_thread_with_config(
+        name,
+        stack,
+        run,
+        0x02,
+        true,
+        Core::None,
+        msg::MailboxConfig::UNBOUNDED,
+    )?;
+    msg::Message::new(sup)
+        .send(pid)
+        .expect("alkyn: supervisor died before it started");
+    Ok(pid)
+}
+
+pub struct Supervisor {
+    strategy: Strategy,
+    intensity: Intensity,
+    children: Vec<Child>,
+}
+
+impl Supervisor {
+    pub fn new(
+        strategy: Strategy,
+        max_restarts: u32,
+        max_ticks: u32,
+        children: Vec<ChildSpec>,
+    ) -> Supervisor {
+        Supervisor {
+            strategy,
+            intensity: Intensity::new(max_restarts, max_ticks),
+            children: children.into_iter().map(Child::new).collect(),
+        }
+    }
+
+    /// Start the supervisor process, which then starts the children in
+    /// order.
+    pub fn start(self, name: &'static str, stack: &'static mut [u32]) -> Result<Pid, u8> {
+        spawn(self, name, stack, Self::run)
+    }
+
+    fn run() -> ! {
+        thread::trap_exit(true);
+        let mut sup = *msg::receive_of::<Supervisor>();
+
+        for i in 0..sup.children.len() {
+            if sup.children[i].start().is_err() {
+                shutdown(&mut sup.children, ExitReason::Error("child failed to start"));
+            }
+        }
+
+        loop {
+            let exit = *msg::receive_of::<Exit>();
+            match sup.children.iter().position(|c| c.pid == Some(exit.pid)) {
+                Some(i) => sup.child_exited(i, exit.reason),
+                // Something else we are linked to went down
+                None if exit.reason != ExitReason::Normal => {
+                    shutdown(&mut sup.children, exit.reason)
+                }
+                None => (),
+            }
+        }
+    }
+
+    fn child_exited(&mut self, i: usize, reason: ExitReason) {
+        if !note_exit(&mut self.children, &mut self.intensity, i, reason) {
+            return;
+        }
+
+        let affected = match self.strategy {
+            Strategy::OneForOne => i..i + 1,
+            Strategy::OneForAll => 0..self.children.len(),
+            Strategy::RestForOne => i..self.children.len(),
+        };
+        for j in affected.clone().rev() {
+            self.children[j].stop();
+        }
+        for j in affected {
+            // Temporary children are never restarted, only the one that
+            // terminated is certain not to be temporary
+            if j == i || self.children[j].spec.restart != Restart::Temporary {
+                restart(&mut self.children, j);
+            } else {
+                self.children[j].release();
+            }
+        }
+    }
+}
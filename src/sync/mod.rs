@@ -2,8 +2,7 @@
 
 use core::sync::atomic::{Ordering};
 
-use crate::hal;
-use crate::{pac, processor};
+use crate::{port, processor};
 
 mod mutex;
 use defmt::Format;
@@ -52,13 +51,18 @@ unsafe fn unclaim_lock(lock: LockToken) -> LockToken {
     lock
 }
 
+/// Guards `LOCK_OWNERS` between cores.
+fn claim_sync_lock() {
+    while !port::spinlock_try_claim(SYNC_LOCK) {}
+}
+
 impl Spinlock {
     #[inline]
     pub fn new() -> Option<Self> {
         unsafe {
-            let _sync_lock = hal::sio::Spinlock::<SYNC_LOCK>::claim();
+            claim_sync_lock();
             let lock_index = claim_unused();
-            hal::sio::Spinlock::<SYNC_LOCK>::release();
+            port::spinlock_release(SYNC_LOCK);
 
             lock_index.and_then(|index| Some(Self { lock: index }))
         }
@@ -72,9 +76,9 @@ impl Spinlock {
 
     pub fn deinit(&self) -> LockToken {
         unsafe {
-            let _sync_lock = hal::sio::Spinlock::<SYNC_LOCK>::claim();
+            claim_sync_lock();
             let lock_index = unclaim_lock(self.lock);
-            hal::sio::Spinlock::<SYNC_LOCK>::release();
+            port::spinlock_release(SYNC_LOCK);
 
             self.release();
 
@@ -83,10 +87,7 @@ impl Spinlock {
     }
 
     pub fn try_claim(&self) -> Option<&Self> {
-        let sio = unsafe { &*pac::SIO::ptr() };
-        let lock = sio.spinlock[self.lock.0 as usize].read().bits();
-
-        if lock > 0 {
+        if port::spinlock_try_claim(self.lock.0 as usize) {
             Some(self)
         } else {
             None
@@ -103,8 +104,7 @@ impl Spinlock {
     }
 
     pub unsafe fn release(&self) {
-        let sio = &*pac::SIO::ptr();
-        sio.spinlock[self.lock.0 as usize].write_with_zero(|b| b.bits(1))
+        port::spinlock_release(self.lock.0 as usize)
     }
 
     pub fn critical_section<F, R>(&self, f: F) -> R
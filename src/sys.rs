@@ -0,0 +1,106 @@
+//! # System messages for GenServers.
+//!
+//! Every [`GenServer`] process answers these on its own, without its
+//! implementation seeing them, which makes them handy for debugging in the
+//! field: [`get_state`] fetches a snapshot of the state that can be
+//! logged with `defmt`, [`replace_state`] swaps it, and [`suspend`] and
+//! [`resume`] pause and restart the handling of ordinary messages. A
+//! suspended server keeps answering system messages, everything else
+//! waits in its mailbox.
+extern crate alloc;
+use alloc::boxed::Box;
+use defmt::Format;
+
+use crate::genserver::{self, CallError, From, GenServer};
+use crate::thread::{self, MonitorRef, Pid};
+
+/// A system request to a server with state `S`
+pub(crate) struct System<S> {
+    from: Pid,
+    tag: MonitorRef,
+    request: SysRequest<S>,
+}
+
+/// Sends the caller of [`get_state`] its snapshot of the state
+type Snapshot<S> = Box<dyn FnOnce(&S, Pid, MonitorRef)>;
+
+enum SysRequest<S> {
+    GetState(Snapshot<S>),
+    ReplaceState(Box<dyn FnOnce(S) -> S>),
+    Suspend,
+    Resume,
+}
+
+/// Fetch what `snapshot` makes of the state of the server `pid`, e.g. the
+/// fields worth logging, or `T::State::clone` for a state that implements
+/// `Format` itself.
+pub fn get_state<T, F>(
+    pid: Pid,
+    snapshot: fn(&T::State) -> F,
+    timeout: u32,
+) -> Result<F, CallError>
+where
+    T: GenServer,
+    F: Format + 'static,
+{
+    let reply = move |state: &T::State, from, tag| {
+        genserver::reply(From::new(from, tag), snapshot(state));
+    };
+    request::<T, F>(pid, SysRequest::GetState(Box::new(reply)), timeout)
+}
+
+/// Replace the state of the server `pid` with what `f` makes of it.
+pub fn replace_state<T, F>(pid: Pid, f: F, timeout: u32) -> Result<(), CallError>
+where
+    T: GenServer,
+    F: FnOnce(T::State) -> T::State + 'static,
+{
+    request::<T, ()>(pid, SysRequest::ReplaceState(Box::new(f)), timeout)
+}
+
+/// Stop the server `pid` from handling anything but system messages,
+/// until [`resume`]d.
+pub fn suspend<T: GenServer>(pid: Pid, timeout: u32) -> Result<(), CallError> {
+    request::<T, ()>(pid, SysRequest::Suspend, timeout)
+}
+
+/// Let the server `pid` handle its messages again.
+pub fn resume<T: GenServer>(pid: Pid, timeout: u32) -> Result<(), CallError> {
+    request::<T, ()>(pid, SysRequest::Resume, timeout)
+}
+
+fn request<T: GenServer, R: 'static>(
+    pid: Pid,
+    request: SysRequest<T::State>,
+    timeout: u32,
+) -> Result<R, CallError> {
+    let from = thread::get_current_pid();
+    genserver::call_with(pid, |tag| System { from, tag, request }, Some(timeout))
+}
+
+/// Serve a system request in the server process, returns the state to go
+/// on with.
+pub(crate) fn handle<S: 'static>(system: System<S>, state: S, suspended: &mut bool) -> S {
+    let (from, tag) = (system.from, system.tag);
+    match system.request {
+        SysRequest::GetState(reply) => {
+            reply(&state, from, tag);
+            state
+        }
+        SysRequest::ReplaceState(f) => {
+            let state = f(state);
+            genserver::reply(From::new(from, tag), ());
+            state
+        }
+        SysRequest::Suspend => {
+            *suspended = true;
+            genserver::reply(From::new(from, tag), ());
+            state
+        }
+        SysRequest::Resume => {
+            *suspended = false;
+            genserver::reply(From::new(from, tag), ());
+            state
+        }
+    }
+}
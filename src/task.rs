@@ -0,0 +1,115 @@
+//! # Tasks.
+//!
+//! A [`Task`] runs one function in a new process and sends its result back
+//! to the process that spawned it, which picks it up with
+//! [`join`](Task::join). The spawner monitors the task, so a task that
+//! exits without a result is noticed right away.
+use core::marker::PhantomData;
+
+use crate::thread::{self, msg, Core, Down, ExitReason, MonitorRef, Pid, Stack};
+
+/// Why [`Task::join`] got no result
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum TaskError {
+    /// No result within the timeout, the task was killed
+    Timeout,
+    /// The task exited with this reason before returning
+    Exited(ExitReason),
+}
+
+/// The result of a task, sent to its spawner
+struct Done<R> {
+    pid: Pid,
+    value: R,
+}
+
+/// A function running in its own process, returning an `R`
+pub struct Task<R> {
+    pid: Pid,
+    monitor_ref: MonitorRef,
+    _result: PhantomData<fn() -> R>,
+}
+
+impl<R: 'static> Task<R> {
+    /// Run `f` in a new process, its result is sent to the caller.
+    ///
+    /// Like [`thread::spawn`], the caller has to be privileged, this fails
+    /// with 2 otherwise. The task itself is not, so it can't spawn tasks of
+    /// its own. Fails with 7 before the kernel is started.
+    pub fn spawn<F>(f: F) -> Result<Task<R>, u8>
+    where
+        F: FnOnce() -> R + Send + 'static,
+    {
+        if !thread::is_started() {
+            return Err(7); // No process to send the result to
+        }
+        let owner = thread::get_current_pid();
+        let pid = thread::spawn_with_config(
+            "task",
+            Stack::Heap(thread::PROCESS_STACK),
+            move || {
+                let done = Done {
+                    pid: thread::get_current_pid(),
+                    value: f(),
+                };
+                // Nothing to do if the owner is gone
+                let _ = msg::Message::new(done).send(owner);
+            },
+            0x1,
+            false,
+            Core::None,
+            msg::MailboxConfig::UNBOUNDED,
+        )?;
+
+        // Even if the task already finished, its result is queued before
+        // the `Down` this sends
+        let monitor_ref = thread::monitor(pid);
+        Ok(Task {
+            pid,
+            monitor_ref,
+            _result: PhantomData,
+        })
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Wait up to `timeout` ticks for the result of the task.
+    ///
+    /// A task that is still running at the timeout is killed. Only the
+    /// process that spawned the task gets its result.
+    #[doc(alias = "await")]
+    pub fn join(self, timeout: u32) -> Result<R, TaskError> {
+        let (pid, monitor_ref) = (self.pid, self.monitor_ref);
+        let is_down = |m: &dyn core::any::Any| {
+            m.downcast_ref::<Down>().map_or(false, |d| d.monitor_ref == monitor_ref)
+        };
+
+        let m = msg::receive_matching_timeout(
+            |m| match m.downcast_ref::<Done<R>>() {
+                Some(done) => done.pid == pid,
+                None => is_down(m),
+            },
+            timeout,
+        );
+        if m.is_err() {
+            thread::send_exit(pid, ExitReason::Kill);
+        }
+        thread::demonitor(monitor_ref);
+        // Whatever the task left behind
+        let _ = msg::check_receive_matching(is_down);
+        let _ = msg::check_receive_matching(|m| {
+            m.downcast_ref::<Done<R>>().map_or(false, |d| d.pid == pid)
+        });
+
+        let m = m.map_err(|_| TaskError::Timeout)?;
+        match m.downcast::<Done<R>>() {
+            Ok(done) => Ok(done.value),
+            Err(m) => {
+                let reason = m.downcast_ref::<Down>().map_or(ExitReason::NoProc, |d| d.reason);
+                Err(TaskError::Exited(reason))
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Thread entry points
+//!
+//! A thread runs a plain `fn() -> R`. It may loop forever (`R = !`) or
+//! return, in which case the kernel terminates it with the
+//! [`ExitReason`] the returned value stands for, see [`IntoExitReason`].
+//!
+//! Closures are supported as well, see [`spawn`](super::spawn). They are
+//! boxed on the heap and freed once the thread has taken them, or when it
+//! is terminated before its first run.
+//!
+//! The port only knows how to start an `fn(usize) -> !`, so every entry
+//! point is wrapped in a trampoline that calls it and exits with its
+//! result.
+extern crate alloc;
+use alloc::boxed::Box;
+
+use super::{link, ExitReason};
+
+/// Values a thread can return, turned into the reason it exits with.
+pub trait IntoExitReason {
+    fn into_exit_reason(self) -> ExitReason;
+}
+
+impl IntoExitReason for ! {
+    fn into_exit_reason(self) -> ExitReason {
+        self
+    }
+}
+
+impl IntoExitReason for () {
+    fn into_exit_reason(self) -> ExitReason {
+        ExitReason::Normal
+    }
+}
+
+impl IntoExitReason for ExitReason {
+    fn into_exit_reason(self) -> ExitReason {
+        self
+    }
+}
+
+impl<T: IntoExitReason> IntoExitReason for Result<T, ExitReason> {
+    fn into_exit_reason(self) -> ExitReason {
+        match self {
+            Ok(v) => v.into_exit_reason(),
+            Err(reason) => reason,
+        }
+    }
+}
+
+impl<T: IntoExitReason> IntoExitReason for Result<T, &'static str> {
+    fn into_exit_reason(self) -> ExitReason {
+        match self {
+            Ok(v) => v.into_exit_reason(),
+            Err(e) => ExitReason::Error(e),
+        }
+    }
+}
+
+/// An entry point ready to be framed on a stack: the trampoline the
+/// port starts and the argument it is started with.
+#[derive(Clone, Copy)]
+pub(crate) struct Entry {
+    pub(crate) trampoline: fn(usize) -> !,
+    pub(crate) arg: usize,
+    /// Frees a boxed closure that was never run, `None` for plain `fn`s.
+    /// A closure is consumed by its first run, so only plain `fn`s can be
+    /// started again.
+    free: Option<fn(usize)>,
+    /// Whether the closure was claimed, by its thread or by `terminate`
+    taken: bool,
+}
+
+impl Entry {
+    pub(crate) fn new<R: IntoExitReason>(handler_fn: fn() -> R) -> Entry {
+        Entry {
+            trampoline: run::<R>,
+            arg: handler_fn as usize,
+            free: None,
+            taken: false,
+        }
+    }
+
+    pub(crate) fn from_closure<F, R>(f: F) -> Entry
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoExitReason,
+    {
+        Entry {
+            trampoline: run_closure::<F, R>,
+            arg: Box::into_raw(Box::new(f)) as usize,
+            free: Some(free_closure::<F>),
+            taken: false,
+        }
+    }
+
+    pub(crate) fn restartable(&self) -> bool {
+        self.free.is_none()
+    }
+
+    /// Drop an entry whose thread was never created.
+    pub(crate) fn free(self) {
+        if let Some(free) = self.free {
+            free(self.arg);
+        }
+    }
+
+    /// Claim the boxed closure, returns false if it was already claimed.
+    ///
+    /// Call inside a critical section.
+    fn take(&mut self) -> bool {
+        !core::mem::replace(&mut self.taken, true)
+    }
+}
+
+/// Free the closure of the thread in slot `idx` if it never took it, for
+/// a thread terminated before its first run.
+///
+/// Call inside a critical section.
+pub(crate) fn free_untaken(idx: usize) {
+    let entry = unsafe { &mut super::ALKYN_THREADS_GLOBAL.threads[idx].entry };
+    if entry.free.is_some() && entry.take() {
+        entry.free();
+    }
+}
+
+fn run<R: IntoExitReason>(arg: usize) -> ! {
+    // Safety: `arg` was made from an `fn() -> R` in `Entry::new`
+    let handler_fn: fn() -> R = unsafe { core::mem::transmute(arg) };
+    link::exit(handler_fn().into_exit_reason())
+}
+
+fn run_closure<F, R>(arg: usize) -> !
+where
+    F: FnOnce() -> R,
+    R: IntoExitReason,
+{
+    let claimed = unsafe {
+        let cs = critical_section::acquire();
+        let idx = super::current_idx();
+        let claimed = super::ALKYN_THREADS_GLOBAL.threads[idx].entry.take();
+        critical_section::release(cs);
+        claimed
+    };
+    if !claimed {
+        // Terminated before it got here, the closure is already freed
+        link::exit(ExitReason::Killed)
+    }
+    // Safety: `arg` was boxed in `Entry::from_closure` and was just
+    // claimed, so nothing else frees it
+    let f = unsafe { Box::from_raw(arg as *mut F) };
+    link::exit(f().into_exit_reason())
+}
+
+fn free_closure<F>(arg: usize) {
+    // Safety: as in `run_closure`, which never took this box
+    drop(unsafe { Box::from_raw(arg as *mut F) });
+}
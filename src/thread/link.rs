@@ -0,0 +1,186 @@
+//! Links and exit signals
+//!
+//! Two linked processes share their fate. When one of them terminates,
+//! the other gets an exit signal carrying the [`ExitReason`]. Unless the
+//! reason is `Normal`, that signal terminates it in turn with the same
+//! reason, which then spreads on to its own links.
+//!
+//! A process that sets [`trap_exit`] is not terminated by exit signals.
+//! They are delivered to its mailbox as an [`Exit`] message instead, sent
+//! from the process that exited. Only [`ExitReason::Kill`], sent directly
+//! with [`send_exit`], cannot be trapped.
+extern crate alloc;
+use alloc::vec::Vec;
+use defmt::Format;
+
+use super::{
+    entry, monitor, msg, registry, systick, Pid, ThreadStatus, ALKYN_THREADS_GLOBAL, MAX_THREADS,
+};
+
+// Init needed for static allocation
+const INIT: Vec<Pid> = Vec::new();
+
+/// Links of every thread slot, kept in both directions.
+static mut ALKYN_LINKS: [Vec<Pid>; MAX_THREADS] = [INIT; MAX_THREADS];
+
+/// Why a process terminated
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub enum ExitReason {
+    /// Finished its work, linked processes are left running
+    Normal,
+    /// Killed with `kill_thread` or an exit signal of `Kill`
+    Killed,
+    /// Sent with [`send_exit`] to kill a process even if it traps exits.
+    /// Spreads to links as `Killed`.
+    Kill,
+    /// The process to link to was not alive
+    NoProc,
+    /// Stopped by its supervisor, or a supervisor giving up
+    Shutdown,
+    /// Anything else
+    Error(&'static str),
+}
+
+/// An exit signal turned into a message by [`trap_exit`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct Exit {
+    /// The process that exited, or sent the signal with [`send_exit`]
+    pub pid: Pid,
+    pub reason: ExitReason,
+}
+
+/// Link the calling process to `pid`.
+///
+/// Linking to a dead process sends the caller an exit signal with
+/// `ExitReason::NoProc` straight away.
+pub fn link(pid: Pid) {
+    let me = super::get_current_pid();
+    if pid == me {
+        return;
+    }
+    unsafe {
+        let cs = critical_section::acquire();
+        if super::is_alive(pid) {
+            if !ALKYN_LINKS[me.idx()].contains(&pid) {
+                ALKYN_LINKS[me.idx()].push(pid);
+                ALKYN_LINKS[pid.idx()].push(me);
+            }
+        } else if let Some(reason) = signal(me, pid, ExitReason::NoProc) {
+            terminate(me, reason);
+        }
+        critical_section::release(cs)
+    }
+    stop_if_dead();
+}
+
+/// Remove the link between the calling process and `pid`, if any.
+pub fn unlink(pid: Pid) {
+    let me = super::get_current_pid();
+    unsafe {
+        let cs = critical_section::acquire();
+        ALKYN_LINKS[me.idx()].retain(|p| *p != pid);
+        if super::is_alive(pid) {
+            ALKYN_LINKS[pid.idx()].retain(|p| *p != me);
+        }
+        critical_section::release(cs)
+    }
+}
+
+/// Set whether the calling process traps exit signals, returning the
+/// previous setting.
+pub fn trap_exit(trap: bool) -> bool {
+    unsafe {
+        let cs = critical_section::acquire();
+        let thr = &mut ALKYN_THREADS_GLOBAL.threads[super::current_idx()];
+        let prev = thr.trap_exit;
+        thr.trap_exit = trap;
+        critical_section::release(cs);
+        prev
+    }
+}
+
+/// Terminate the calling process with `reason`.
+///
+/// Its mailbox is freed, links get an exit signal and monitors a `Down`
+/// message, as for any other termination. Returning from a thread's entry
+/// point ends up here as well.
+pub fn exit(reason: ExitReason) -> ! {
+    terminate(super::get_current_pid(), reason);
+    loop {
+        systick::run_ctxswitch();
+    }
+}
+
+/// Send an exit signal with `reason` to `pid`, as if the caller had
+/// exited. Does nothing if `pid` is already dead.
+pub fn send_exit(pid: Pid, reason: ExitReason) {
+    unsafe {
+        let cs = critical_section::acquire();
+        if let Some(reason) = signal(pid, super::get_current_pid(), reason) {
+            terminate(pid, reason);
+        }
+        critical_section::release(cs)
+    }
+    stop_if_dead();
+}
+
+/// Terminate `pid` with `reason`, send exit signals to its links and
+/// `Down` messages to its monitors.
+///
+/// Its slot is freed for reuse, any messages left in its mailbox are
+/// dropped and its registry entries removed.
+pub(crate) fn terminate(pid: Pid, reason: ExitReason) {
+    unsafe {
+        let cs = critical_section::acquire();
+        // Worked through in a loop, a long chain of links would overflow
+        // a small stack if followed recursively
+        let mut dying = alloc::vec![(pid, reason)];
+        while let Some((pid, reason)) = dying.pop() {
+            if !super::is_alive(pid) {
+                continue;
+            }
+            ALKYN_THREADS_GLOBAL.threads[pid.idx()].status = ThreadStatus::Dead;
+            msg::clear_mailbox(pid.idx());
+            registry::unregister_pid(pid);
+            monitor::process_down(pid, reason);
+            super::free_stack(pid.idx());
+            entry::free_untaken(pid.idx());
+
+            for linked in core::mem::take(&mut ALKYN_LINKS[pid.idx()]) {
+                ALKYN_LINKS[linked.idx()].retain(|p| *p != pid);
+                if let Some(reason) = signal(linked, pid, reason) {
+                    dying.push((linked, reason));
+                }
+            }
+        }
+        critical_section::release(cs)
+    }
+}
+
+/// Deliver an exit signal from `from` to `to`.
+///
+/// Returns the reason `to` has to terminate with, if it does. Call inside
+/// a critical section.
+fn signal(to: Pid, from: Pid, reason: ExitReason) -> Option<ExitReason> {
+    if !super::is_alive(to) {
+        return None;
+    }
+    let trapping = unsafe { ALKYN_THREADS_GLOBAL.threads[to.idx()].trap_exit };
+    match reason {
+        ExitReason::Kill => Some(ExitReason::Killed),
+        _ if trapping => {
+            msg::notify(to, from, Exit { pid: from, reason });
+            None
+        }
+        ExitReason::Normal => None,
+        _ => Some(reason),
+    }
+}
+
+/// Never return to a process an exit signal has just terminated, or one
+/// terminated while it was still running on the other core.
+pub(crate) fn stop_if_dead() {
+    while !super::is_alive(super::get_current_pid()) {
+        systick::run_ctxswitch();
+    }
+}
@@ -1,15 +1,28 @@
 //! Use threads and message passing
 
 use core::{marker::PhantomData};
-use cortex_m::{peripheral::SYST};
 use defmt::error;
 
 extern crate alloc;
 use alloc::vec::Vec;
 
-use crate::processor;
+use crate::{port, processor};
+mod entry;
+mod link;
+mod monitor;
 pub mod msg;
+mod pid;
+mod stack;
 pub mod registry;
+pub(crate) use link::terminate;
+pub(crate) use entry::Entry;
+pub use entry::IntoExitReason;
+pub use link::{exit, link, send_exit, trap_exit, unlink, Exit, ExitReason};
+pub(crate) use monitor::is_monitoring;
+pub use monitor::{demonitor, monitor, Down, MonitorRef};
+pub use pid::Pid;
+pub(crate) use stack::PROCESS_STACK;
+pub use stack::Stack;
 
 pub mod systick;
 
@@ -20,11 +33,12 @@ const CORES: usize = 2;
 pub struct ThreadingState<'a> {
     cores: [CoreState; CORES],
     inited: bool,
-    add_idx: usize,
     // threads: [ThreadControlBlock<'a>; MAX_THREADS],
     threads: Vec<ThreadControlBlock<'a>>,
     counter: u64,
     prev_cnt: u32,
+    /// Kernel ticks since start, wraps around
+    ticks: u32,
 }
 
 #[repr(C)]
@@ -33,6 +47,8 @@ pub struct CoreState {
     current: usize,
     next: usize,
     idx: usize,
+    /// Index of this core's idle thread
+    idle: usize,
 }
 
 #[repr(C)]
@@ -41,6 +57,10 @@ enum ThreadStatus {
     Ready,
     Sleeping,
     MailPending, //
+    /// Waiting for room in a full mailbox, see `msg::Overflow::Block`
+    SendPending,
+    /// Slot is free to be reused
+    Dead,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -66,6 +86,14 @@ impl Core {
         converted
     }
 
+    pub fn from_index(core: usize) -> Core {
+        match core {
+            0 => Core::Core0,
+            1 => Core::Core1,
+            _ => Core::None,
+        }
+    }
+
     pub fn get_allowed() -> [Core; 2] {
         let current_core = processor::get_current_core();
         Core::from_slice(&[current_core])
@@ -78,17 +106,49 @@ impl Core {
 struct ThreadControlBlock<'a> {
     // start fields used in assembly, do not reorder them
     /// current stack pointer of this thread
-    sp: u32,
+    sp: usize,
     privileged: u32, // make it a word, assembly is easier. FIXME
     // end fields used in assembly
     priority: u8,
     status: ThreadStatus,
     sleep_ticks: u32,
+    /// Ticks left before a timed receive gives up
+    receive_ticks: Option<u32>,
+    timed_out: bool,
+    /// Exit signals arrive as messages, see [`trap_exit`]
+    trap_exit: bool,
+    /// Core currently running this thread
     core: Core,
     affinity: Core,
+    /// Bumped every time the slot is reused, see [`Pid`]
+    generation: u16,
+    /// Kept to restart the thread in place, see [`restart_thread`]
+    stack: *mut [u32],
+    /// Whether `stack` came from the heap, it is null once freed
+    stack_owned: bool,
+    /// Process keeping the slot from reuse while dead, see [`reserve_slot`]
+    keeper: Option<Pid>,
+    entry: Entry,
+    name: &'static str,
     _stack: PhantomData<&'a mut [u32]>,
 }
 
+impl ThreadControlBlock<'_> {
+    /// Return the heap stack if the thread is dead and no core runs on it
+    /// any more. Call inside a critical section.
+    fn free_stack(&mut self) {
+        if self.stack_owned
+            && !self.stack.is_null()
+            && self.status == ThreadStatus::Dead
+            && self.core == Core::None
+        {
+            unsafe { stack::free(self.stack) };
+            self.stack =
+                core::ptr::slice_from_raw_parts_mut(core::ptr::null_mut(), self.stack.len());
+        }
+    }
+}
+
 #[no_mangle]
 static mut __ALKYN_THREADS_GLOBAL_PTR: u32 = 0;
 pub static mut ALKYN_THREADS_GLOBAL: ThreadingState = ThreadingState {
@@ -96,18 +156,29 @@ pub static mut ALKYN_THREADS_GLOBAL: ThreadingState = ThreadingState {
         current: 0,
         next: 0,
         idx: 0,
+        idle: 0,
     }; CORES],
     inited: false,
-    add_idx: CORES,
     threads: Vec::new(),
     counter: 0,
     prev_cnt: 0,
+    ticks: 0,
 };
 
 impl ThreadingState<'static> {
     pub fn set_next_to_curr(&mut self) {
         let core: usize = processor::get_current_core().into();
-        self.cores[core].current = self.cores[core].next;
+        let core_state = &mut self.cores[core];
+        if core_state.current > 1 && core_state.current != core_state.next {
+            // Safety: current always points into `threads`
+            let prev = unsafe { &mut *(core_state.current as *mut ThreadControlBlock) };
+            let cs = unsafe { critical_section::acquire() };
+            prev.core = Core::None;
+            // Its context is saved, nothing runs on its stack any more
+            prev.free_stack();
+            unsafe { critical_section::release(cs) };
+        }
+        core_state.current = core_state.next;
     }
 }
 
@@ -125,6 +196,11 @@ pub fn get_counter() -> u64 {
     counter
 }
 
+/// Kernel ticks since the scheduler started, wrapping around.
+pub fn get_ticks() -> u32 {
+    unsafe { ALKYN_THREADS_GLOBAL.ticks }
+}
+
 // Safety: read_only
 pub fn get_current_thread_ptr() -> usize {
     unsafe { processor::disable_interrupts() }
@@ -148,6 +224,58 @@ pub fn get_current_thread_idx() -> usize {
     idx
 }
 
+/// Slot of the calling thread.
+///
+/// Call inside a critical section. Unlike [`get_current_thread_idx`] it
+/// leaves interrupts alone, so they stay off while the kernel is locked.
+pub(crate) fn current_idx() -> usize {
+    let core: usize = processor::get_current_core().into();
+    unsafe { ALKYN_THREADS_GLOBAL.cores[core].idx }
+}
+
+/// Mark the thread in slot `idx` as waiting with `status`.
+///
+/// Call inside a critical section. A thread terminated while still running
+/// on its core stays `Dead`, so nothing wakes it up again.
+pub(crate) fn park(idx: usize, status: ThreadStatus) {
+    let thr = unsafe { &mut ALKYN_THREADS_GLOBAL.threads[idx] };
+    if thr.status != ThreadStatus::Dead {
+        thr.status = status;
+    }
+}
+
+/// Get the `Pid` of the calling thread.
+pub fn get_current_pid() -> Pid {
+    let cs = unsafe { critical_section::acquire() };
+    let idx = current_idx();
+    let pid = Pid::new(idx, unsafe { ALKYN_THREADS_GLOBAL.threads[idx].generation });
+
+    unsafe { critical_section::release(cs) }
+    pid
+}
+
+/// Whether the kernel is started, before that there is no calling process.
+pub(crate) fn is_started() -> bool {
+    let cs = unsafe { critical_section::acquire() };
+    let started = unsafe { ALKYN_THREADS_GLOBAL.inited };
+    unsafe { critical_section::release(cs) }
+    started
+}
+
+/// Check whether `pid` still refers to a running process.
+pub fn is_alive(pid: Pid) -> bool {
+    let cs = unsafe { critical_section::acquire() };
+    let handler = unsafe { &mut ALKYN_THREADS_GLOBAL };
+
+    let alive = match handler.threads.get(pid.idx()) {
+        Some(thr) => thr.generation == pid.generation() && thr.status != ThreadStatus::Dead,
+        None => false,
+    };
+
+    unsafe { critical_section::release(cs) }
+    alive
+}
+
 pub fn get_next_thread_ptr() -> usize {
     unsafe { processor::disable_interrupts() };
     let core: usize = processor::get_current_core().into();
@@ -159,7 +287,7 @@ pub fn get_next_thread_ptr() -> usize {
 }
 
 /// Initialize the switcher system
-pub fn init(syst: &mut SYST, ticks: u32) -> ! {
+pub fn init(syst: &mut port::Systick, ticks: u32) -> ! {
     crate::multi::init_cores();
     unsafe {
         let cs = critical_section::acquire();
@@ -184,19 +312,20 @@ pub fn init(syst: &mut SYST, ticks: u32) -> ! {
 ///
 /// Unsafe as this should only be called once per core, and no guards
 /// to make sure you don't do it twice
-unsafe fn create_idle_thr(core: Core, _idx: usize) {
-    static mut idle_stack: [u32; 64] = [0xDEADBEEF; 64];
+unsafe fn create_idle_thr(core: Core, idx: usize) {
+    static mut idle_stacks: [[u32; 64]; CORES] = [[0xDEADBEEF; 64]; CORES];
     match create_tcb(
-        &mut idle_stack,
-        || loop {
+        "idle",
+        &mut idle_stacks[idx],
+        Entry::new::<!>(|| loop {
             processor::wait_for_event();
-        },
+        }),
         0x00,
         false,
         core,
     ) {
         Ok(tcb) => {
-            insert_tcb(tcb); // BUG!
+            ALKYN_THREADS_GLOBAL.cores[idx].idle = insert_tcb(tcb).idx();
         }
         _ => defmt::error!("Alkyn: Could not create idle thread for core!"),
     };
@@ -205,49 +334,210 @@ unsafe fn create_idle_thr(core: Core, _idx: usize) {
 /// Create a thread with default config.
 ///
 /// This can be ran at any time. Threads have no core affinity and no privileges.
-pub fn create_thread(
+/// `handler_fn` may loop forever or return, see [`IntoExitReason`] for what
+/// returning means.
+pub fn create_thread<R: IntoExitReason>(
+    name: &'static str,
+    stack: impl Into<Stack>,
+    handler_fn: fn() -> R,
+) -> Result<Pid, u8> {
+    create_thread_with_config(
+        name,
+        stack,
+        handler_fn,
+        0x01,
+        false,
+        Core::None,
+        msg::MailboxConfig::UNBOUNDED,
+    )
+}
+
+/// Create a thread.
+///
+/// `stack` is either caller-owned memory or a [`Stack::Heap`] of the given
+/// size, which the kernel frees once the thread has exited. `mailbox` sets
+/// the capacity and overflow policy of its mailbox. Fails with 6 if the
+/// stack could not be allocated.
+pub fn create_thread_with_config<R: IntoExitReason>(
+    name: &'static str,
+    stack: impl Into<Stack>,
+    handler_fn: fn() -> R,
+    priority: u8,
+    priviliged: bool,
+    affinity: Core,
+    mailbox: msg::MailboxConfig,
+) -> Result<Pid, u8> {
+    create_thread_from_entry(
+        name,
+        stack,
+        Entry::new(handler_fn),
+        priority,
+        priviliged,
+        affinity,
+        mailbox,
+    )
+}
+
+/// Spawn a thread running the closure `f`, with the default config of
+/// [`create_thread`].
+///
+/// The closure is boxed on the heap, so it can carry a channel, a peer
+/// `Pid` or any other value into the thread, e.g.
+/// `spawn("worker", stack, move || serve(peer))`.
+pub fn spawn<F, R>(name: &'static str, stack: impl Into<Stack>, f: F) -> Result<Pid, u8>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: IntoExitReason,
+{
+    spawn_with_config(
+        name,
+        stack,
+        f,
+        0x01,
+        false,
+        Core::None,
+        msg::MailboxConfig::UNBOUNDED,
+    )
+}
+
+/// Spawn a thread calling `handler_fn` with `arg`, see [`spawn`].
+pub fn spawn_with<A, R>(
     name: &'static str,
-    stack: &'static mut [u32],
-    handler_fn: fn() -> !,
-) -> Result<(), u8> {
-    create_thread_with_config(name, stack, handler_fn, 0x01, false, Core::None)
+    stack: impl Into<Stack>,
+    arg: A,
+    handler_fn: fn(A) -> R,
+) -> Result<Pid, u8>
+where
+    A: Send + 'static,
+    R: IntoExitReason + 'static,
+{
+    spawn(name, stack, move || handler_fn(arg))
 }
 
-pub fn create_thread_with_config(
+/// Spawn a thread running the closure `f`, with the options of
+/// [`create_thread_with_config`].
+///
+/// Unlike threads created from a plain `fn`, these can't be restarted
+/// with [`restart_thread`].
+pub fn spawn_with_config<F, R>(
     name: &'static str,
-    stack: &'static mut [u32],
-    handler_fn: fn() -> !,
+    stack: impl Into<Stack>,
+    f: F,
     priority: u8,
     priviliged: bool,
     affinity: Core,
-) -> Result<(), u8> {
+    mailbox: msg::MailboxConfig,
+) -> Result<Pid, u8>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: IntoExitReason,
+{
+    let entry = Entry::from_closure(f);
+    let res = create_thread_from_entry(name, stack, entry, priority, priviliged, affinity, mailbox);
+    if res.is_err() {
+        entry.free();
+    }
+    res
+}
+
+/// Create a thread for an already wrapped entry point, see
+/// [`create_thread_with_config`].
+pub(crate) fn create_thread_from_entry(
+    name: &'static str,
+    stack: impl Into<Stack>,
+    entry: Entry,
+    priority: u8,
+    priviliged: bool,
+    affinity: Core,
+    mailbox: msg::MailboxConfig,
+) -> Result<Pid, u8> {
     unsafe {
         let cs = critical_section::acquire();
         let handler = &mut ALKYN_THREADS_GLOBAL;
         let curr_core: usize = processor::get_current_core().into();
 
-        if handler.threads.len() >= MAX_THREADS {
-            return Err(1); // Too many threads
-        }
-
         if handler.inited && handler.threads[handler.cores[curr_core].idx].privileged == 0 {
+            critical_section::release(cs);
             return Err(2); // Not enough privileges
         }
 
-        match create_tcb(stack, handler_fn, priority, priviliged, affinity) {
-            Ok(tcb) => {
-                let idx = insert_tcb(tcb);
-                registry::set_registry_for_idx(idx, name)
-            }
-            Err(e) => {
-                critical_section::release(cs);
-                defmt::debug!("Error creating thread");
-                return Err(e);
+        let res = insert_thread(name, stack.into(), entry, priority, priviliged, affinity, mailbox);
+        critical_section::release(cs);
+        res
+    }
+}
+
+/// Create a thread without checking the caller's privileges.
+///
+/// Call inside a critical section.
+unsafe fn insert_thread(
+    name: &'static str,
+    stack: Stack,
+    entry: Entry,
+    priority: u8,
+    priviliged: bool,
+    affinity: Core,
+    mailbox: msg::MailboxConfig,
+) -> Result<Pid, u8> {
+    let handler = &mut ALKYN_THREADS_GLOBAL;
+
+    if handler.threads.len() >= MAX_THREADS && find_free_slot().is_none() {
+        return Err(1); // Too many threads
+    }
+
+    let (stack, owned) = match stack {
+        Stack::Static(stack) => (stack as *mut [u32], false),
+        // Checked before allocating, a zero size allocation is undefined
+        Stack::Heap(words) if words < 32 => {
+            error!("Stack size too small");
+            return Err(1);
+        }
+        Stack::Heap(words) => match stack::allocate(words) {
+            Some(stack) => (stack, true),
+            None => return Err(6), // Out of memory
+        },
+    };
+
+    match create_tcb(name, &mut *stack, entry, priority, priviliged, affinity) {
+        Ok(mut tcb) => {
+            tcb.stack_owned = owned;
+            let pid = insert_tcb(tcb);
+            msg::configure_mailbox(pid.idx(), mailbox);
+            registry::set_registry_for_pid(pid, name);
+            Ok(pid)
+        }
+        Err(e) => {
+            if owned {
+                stack::free(stack);
             }
+            defmt::debug!("Error creating thread");
+            Err(e)
         }
+    }
+}
 
-        critical_section::release(cs);
-        Ok(())
+/// Return the heap stack of the thread in slot `idx` if it is dead and no
+/// core runs on it any more. Call inside a critical section.
+///
+/// A thread that exits while running is freed once it is switched out.
+pub(crate) fn free_stack(idx: usize) {
+    unsafe { ALKYN_THREADS_GLOBAL.threads[idx].free_stack() };
+}
+
+/// Wait until the dead process `pid` has been switched out by every core,
+/// after which its stack can be framed again.
+fn wait_switched_out(pid: Pid) {
+    loop {
+        let cs = unsafe { critical_section::acquire() };
+        let thr = unsafe { ALKYN_THREADS_GLOBAL.threads.get(pid.idx()) };
+        let done = thr.map_or(true, |thr| {
+            thr.generation != pid.generation() || thr.core == Core::None
+        });
+        unsafe { critical_section::release(cs) };
+        if done {
+            return;
+        }
+        sleep(1);
     }
 }
 
@@ -265,6 +555,7 @@ pub fn sleep(ticks: u32) {
 pub fn run_tick() {
     let cs = unsafe { critical_section::acquire() };
     let handler = unsafe { &mut ALKYN_THREADS_GLOBAL };
+    handler.ticks = handler.ticks.wrapping_add(1);
 
     for thr in handler.threads.iter_mut() {
         if thr.status == ThreadStatus::Sleeping {
@@ -274,6 +565,17 @@ pub fn run_tick() {
                 thr.status = ThreadStatus::Ready;
             }
         }
+
+        if let Some(ticks) = thr.receive_ticks {
+            if ticks > 0 {
+                thr.receive_ticks = Some(ticks - 1);
+            } else {
+                thr.timed_out = true;
+                if thr.status == ThreadStatus::MailPending {
+                    thr.status = ThreadStatus::Ready;
+                }
+            }
+        }
     }
 
     unsafe { critical_section::release(cs) };
@@ -289,83 +591,191 @@ pub fn get_next_thread_idx() -> usize {
         .iter()
         .enumerate()
         .filter(|&(_, x)| Core::get_allowed().contains(&x.affinity))
-        .filter(|&(idx, x)| (idx < handler.add_idx) && (x.status == ThreadStatus::Ready))
+        .filter(|&(_, x)| Core::get_allowed().contains(&x.core))
+        .filter(|&(_, x)| x.status == ThreadStatus::Ready)
         .max_by(|&(_, a), &(_, b)| a.priority.cmp(&b.priority))
     {
         Some((idx, _)) => idx,
-        _ => processor::get_current_core().into(),
+        _ => handler.cores[processor::get_current_core() as usize].idle,
     };
     defmt::trace!("thr - nxt idx: {}", new_idx);
     unsafe { critical_section::release(cs) }
     new_idx
 }
 
-fn create_tcb(
-    stack: &mut [u32],
-    handler_fn: fn() -> !,
+fn create_tcb<'a>(
+    name: &'static str,
+    stack: &'a mut [u32],
+    entry: Entry,
     priority: u8,
     priviliged: bool,
     affinity: Core,
-) -> Result<ThreadControlBlock, u8> {
+) -> Result<ThreadControlBlock<'a>, u8> {
     if stack.len() < 32 {
         error!("Stack size too small");
         return Err(1);
     }
 
-    let idx = stack.len() - 1;
-
-    //let pc: usize = unsafe { core::intrinsics::transmute(handler_fn as *const fn()) };
-    let pc: usize = (handler_fn as *const fn()).to_bits();
-
-    // Init registers
-    stack[idx] = 1 << 24; // xPSR
-    stack[idx - 1] = pc as u32;
-
-    // Fill with dummy vals
-    stack[idx - 2] = 0xFFFFFFFD; // return reg
-    stack[idx - 3] = 0xCCCCCCCC; // R12
-    stack[idx - 4] = 0x33333333; // R3
-    stack[idx - 5] = 0x22222222; // R2
-    stack[idx - 6] = 0x11111111; // R1
-    stack[idx - 7] = 0x00000000; // R0
-    stack[idx - 08] = 0x77777777; // R7
-    stack[idx - 09] = 0x66666666; // R6
-    stack[idx - 10] = 0x55555555; // R5
-    stack[idx - 11] = 0x44444444; // R4
-    stack[idx - 12] = 0xBBBBBBBB; // R11
-    stack[idx - 13] = 0xAAAAAAAA; // R10
-    stack[idx - 14] = 0x99999999; // R9
-    stack[idx - 15] = 0x88888888; // R8
-
-    let sp: usize = unsafe { core::intrinsics::transmute(&stack[stack.len() - 16]) };
+    let sp = port::init_stack(stack, entry.trampoline, entry.arg);
 
     let tcb = ThreadControlBlock {
-        sp: sp as u32,
+        sp: sp,
         priority: priority,
         privileged: priviliged.into(),
         status: ThreadStatus::Ready,
         sleep_ticks: 0,
+        receive_ticks: None,
+        timed_out: false,
+        trap_exit: false,
         core: Core::None,
         affinity: affinity,
+        generation: 0,
+        stack: stack as *mut [u32],
+        stack_owned: false,
+        keeper: None,
+        entry,
+        name,
         _stack: PhantomData,
     };
     Ok(tcb)
 }
 
-fn insert_tcb(tcb: ThreadControlBlock<'static>) -> usize {
+/// Restart the dead process `pid` in place.
+///
+/// Its stack is framed again for the entry point it was created with, its
+/// mailbox emptied and its scheduling state reset, so no new stack is
+/// needed, unless its heap stack was already freed. It keeps its `Pid`,
+/// priority, affinity, mailbox config and registered name. Waits for `pid`
+/// to be switched out if it still runs.
+///
+/// A dead process' slot can be reused by any new thread, unless it was
+/// reserved with [`reserve_slot`].
+///
+/// Fails with 3 if `pid` is still alive, 4 if it does not name a slot or
+/// its slot was reused, 5 if it was spawned from a closure, which its first
+/// run consumed, and 6 if a new stack could not be allocated.
+pub fn restart_thread(pid: Pid) -> Result<(), u8> {
+    wait_switched_out(pid);
+    unsafe {
+        let cs = critical_section::acquire();
+        let handler = &mut ALKYN_THREADS_GLOBAL;
+        let curr_core: usize = processor::get_current_core().into();
+
+        if handler.inited && handler.threads[handler.cores[curr_core].idx].privileged == 0 {
+            critical_section::release(cs);
+            return Err(2); // Not enough privileges
+        }
+
+        let thr = match handler.threads.get_mut(pid.idx()) {
+            Some(thr) if thr.generation == pid.generation() => thr,
+            _ => {
+                critical_section::release(cs);
+                return Err(4); // No such slot, or reused
+            }
+        };
+        if thr.status != ThreadStatus::Dead {
+            critical_section::release(cs);
+            return Err(3); // Still alive
+        }
+        if !thr.entry.restartable() {
+            critical_section::release(cs);
+            return Err(5); // Spawned from a closure
+        }
+
+        if thr.stack.is_null() {
+            // Freed after it exited, get a new one of the same size
+            match stack::allocate(thr.stack.len()) {
+                Some(stack) => thr.stack = stack,
+                None => {
+                    critical_section::release(cs);
+                    return Err(6); // Out of memory
+                }
+            }
+        }
+
+        thr.sp = port::init_stack(&mut *thr.stack, thr.entry.trampoline, thr.entry.arg);
+        thr.status = ThreadStatus::Ready;
+        thr.sleep_ticks = 0;
+        thr.receive_ticks = None;
+        thr.timed_out = false;
+        thr.trap_exit = false;
+        msg::clear_mailbox(pid.idx());
+        registry::set_registry_for_pid(pid, thr.name);
+
+        critical_section::release(cs);
+        Ok(())
+    }
+}
+
+/// Keep the slot of `pid` from being reused once it is dead, so it can be
+/// restarted in place. The reservation lasts until [`release_slot`], or
+/// until the calling process is gone.
+///
+/// Fails with 4 if `pid` does not name a slot, or its slot was reused.
+pub(crate) fn reserve_slot(pid: Pid) -> Result<(), u8> {
+    let keeper = get_current_pid();
+    set_keeper(pid, Some(keeper))
+}
+
+/// Let the slot of `pid` be reused again, see [`reserve_slot`].
+pub(crate) fn release_slot(pid: Pid) -> Result<(), u8> {
+    set_keeper(pid, None)
+}
+
+fn set_keeper(pid: Pid, keeper: Option<Pid>) -> Result<(), u8> {
+    unsafe {
+        let cs = critical_section::acquire();
+        let res = match ALKYN_THREADS_GLOBAL.threads.get_mut(pid.idx()) {
+            Some(thr) if thr.generation == pid.generation() => {
+                thr.keeper = keeper;
+                Ok(())
+            }
+            _ => Err(4), // No such slot, or reused
+        };
+        critical_section::release(cs);
+        res
+    }
+}
+
+/// Find a dead slot that no core is still switching out of, and that no
+/// live process keeps for a restart.
+fn find_free_slot() -> Option<usize> {
+    let threads = unsafe { &ALKYN_THREADS_GLOBAL.threads };
+    let alive = |pid: Pid| {
+        threads.get(pid.idx()).map_or(false, |thr| {
+            thr.generation == pid.generation() && thr.status != ThreadStatus::Dead
+        })
+    };
+    threads.iter().position(|x| {
+        x.status == ThreadStatus::Dead && x.core == Core::None && !x.keeper.map_or(false, alive)
+    })
+}
+
+fn insert_tcb(mut tcb: ThreadControlBlock<'static>) -> Pid {
     unsafe {
         let handler = &mut ALKYN_THREADS_GLOBAL;
-        defmt::trace!("inserting with idx {}", handler.threads.len());
-        handler.add_idx += 1;
-        handler.threads.push(tcb);
-        handler.threads.len()
+        match find_free_slot() {
+            Some(idx) => {
+                defmt::trace!("reusing idx {}", idx);
+                tcb.generation = handler.threads[idx].generation.wrapping_add(1);
+                handler.threads[idx] = tcb;
+                Pid::new(idx, tcb.generation)
+            }
+            None => {
+                defmt::trace!("inserting with idx {}", handler.threads.len());
+                handler.threads.push(tcb);
+                Pid::new(handler.threads.len() - 1, tcb.generation)
+            }
+        }
     }
 }
 
-pub unsafe fn kill_thread(idx: usize) {
-    let cs = critical_section::acquire();
-    let handler = &mut ALKYN_THREADS_GLOBAL;
-    handler.threads.remove(idx);
-    handler.add_idx -= 1;
-    critical_section::release(cs)
+/// Kill a process.
+///
+/// Its slot is freed for reuse, any messages left in its mailbox are
+/// dropped and its registry entries removed. Linked processes get an exit
+/// signal with `ExitReason::Killed`, even if it traps exits the process
+/// itself is always killed. Does nothing if `pid` is already dead.
+pub unsafe fn kill_thread(pid: Pid) {
+    link::terminate(pid, ExitReason::Killed)
 }
@@ -0,0 +1,103 @@
+//! Monitors
+//!
+//! A monitor is a one-way link: the watching process gets a [`Down`]
+//! message when the monitored one terminates, and is never terminated by
+//! it. Every call to [`monitor`] sets up a separate monitor, each with its
+//! own [`MonitorRef`].
+extern crate alloc;
+use alloc::vec::Vec;
+use defmt::Format;
+
+use super::{msg, ExitReason, Pid};
+
+/// Identifies one monitor, see [`monitor`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct MonitorRef(u32);
+
+/// Sent to the watcher when a monitored process terminates
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Format)]
+pub struct Down {
+    pub pid: Pid,
+    pub reason: ExitReason,
+    pub monitor_ref: MonitorRef,
+}
+
+struct Monitor {
+    monitor_ref: MonitorRef,
+    watcher: Pid,
+    target: Pid,
+}
+
+static mut ALKYN_MONITORS: Vec<Monitor> = Vec::new();
+static mut NEXT_REF: u32 = 0;
+
+/// Monitor `pid` from the calling process.
+///
+/// When `pid` terminates, the caller gets a [`Down`] message sent from
+/// `pid`. If it is already dead, the message is sent straight away with
+/// `ExitReason::NoProc`.
+pub fn monitor(pid: Pid) -> MonitorRef {
+    let watcher = super::get_current_pid();
+    unsafe {
+        let cs = critical_section::acquire();
+        let monitor_ref = MonitorRef(NEXT_REF);
+        NEXT_REF = NEXT_REF.wrapping_add(1);
+
+        if super::is_alive(pid) {
+            ALKYN_MONITORS.push(Monitor {
+                monitor_ref,
+                watcher,
+                target: pid,
+            });
+        } else {
+            let down = Down {
+                pid,
+                reason: ExitReason::NoProc,
+                monitor_ref,
+            };
+            msg::notify(watcher, pid, down);
+        }
+        critical_section::release(cs);
+        monitor_ref
+    }
+}
+
+/// Remove a monitor set up by [`monitor`].
+///
+/// A `Down` message that was already sent for it stays in the mailbox.
+pub fn demonitor(monitor_ref: MonitorRef) {
+    unsafe {
+        let cs = critical_section::acquire();
+        ALKYN_MONITORS.retain(|m| m.monitor_ref != monitor_ref);
+        critical_section::release(cs)
+    }
+}
+
+/// Whether the monitor `monitor_ref` is still in place.
+///
+/// Call inside a critical section.
+pub(crate) fn is_monitoring(monitor_ref: MonitorRef) -> bool {
+    unsafe { ALKYN_MONITORS.iter().any(|m| m.monitor_ref == monitor_ref) }
+}
+
+/// Notify everything watching `pid` that it terminated with `reason`, and
+/// drop the monitors `pid` held itself.
+///
+/// Call inside a critical section.
+pub(crate) fn process_down(pid: Pid, reason: ExitReason) {
+    unsafe {
+        ALKYN_MONITORS.retain(|m| {
+            if m.target == pid {
+                let down = Down {
+                    pid,
+                    reason,
+                    monitor_ref: m.monitor_ref,
+                };
+                msg::notify(m.watcher, pid, down);
+                false
+            } else {
+                m.watcher != pid
+            }
+        });
+    }
+}
@@ -0,0 +1,194 @@
+//! Typed channels
+//!
+//! A channel carries values of one type `T` from any number of [`Sender`]s
+//! to a single [`Receiver`]. The type is fixed when the channel is made, so
+//! nothing is boxed as `dyn Any` or checked on the way through.
+//!
+//! Both ends can be moved to other processes, e.g. inside a [`Message`],
+//! and senders can be cloned. The receiver blocks through the scheduler
+//! like [`receive`], it is parked until a send wakes it.
+//!
+//! [`Message`]: super::Message
+//! [`receive`]: super::receive
+//!
+//! # Example
+//! ```ignore
+//! let (tx, rx) = msg::channel::<u32>();
+//! Message::new(tx.clone()).send(worker).unwrap();
+//! let n = rx.recv().unwrap();
+//! ```
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+use super::{ReceiveError, SendError};
+use crate::thread::{self, Pid};
+
+/// State shared by both ends, freed by whichever end is dropped last.
+struct Shared<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+    /// Process parked in a receive, woken by the next send
+    waiting: Option<Pid>,
+}
+
+/// Sending end of a channel, see [`channel`].
+pub struct Sender<T> {
+    shared: *mut Shared<T>,
+}
+
+/// Receiving end of a channel, see [`channel`].
+pub struct Receiver<T> {
+    shared: *mut Shared<T>,
+}
+
+// Safety: the shared state is only touched inside the critical section
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+/// Make a new channel, returning both of its ends.
+pub fn channel<T: 'static>() -> (Sender<T>, Receiver<T>) {
+    let shared = Box::into_raw(Box::new(Shared {
+        queue: VecDeque::new(),
+        senders: 1,
+        receiver: true,
+        waiting: None,
+    }));
+    (Sender { shared }, Receiver { shared })
+}
+
+/// Wake the process parked on `shared`, if any.
+///
+/// Call inside a critical section. Returns whether it should preempt the
+/// current thread.
+fn wake_waiting<T>(shared: &mut Shared<T>) -> bool {
+    match shared.waiting.take() {
+        Some(pid) if thread::is_alive(pid) => super::wake(pid.idx()),
+        _ => false,
+    }
+}
+
+impl<T> Sender<T> {
+    /// Queue `value` for the receiver.
+    ///
+    /// Fails with `SendError::Closed` if the receiver was dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError> {
+        unsafe {
+            let cs = critical_section::acquire();
+            let shared = &mut *self.shared;
+            if !shared.receiver {
+                critical_section::release(cs);
+                return Err(SendError::Closed);
+            }
+            shared.queue.push_back(value);
+
+            let preempt = wake_waiting(shared);
+            critical_section::release(cs);
+            if preempt {
+                thread::systick::run_ctxswitch();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        unsafe {
+            let cs = critical_section::acquire();
+            (*self.shared).senders += 1;
+            critical_section::release(cs)
+        }
+        Sender {
+            shared: self.shared,
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let cs = critical_section::acquire();
+            let shared = &mut *self.shared;
+            shared.senders -= 1;
+            let last = shared.senders == 0;
+            if last {
+                // Let a blocked receiver see it is disconnected
+                wake_waiting(shared);
+            }
+            let free = last && !shared.receiver;
+            critical_section::release(cs);
+            if free {
+                drop(Box::from_raw(self.shared));
+            }
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Take the oldest value, if there is one.
+    ///
+    /// Fails with `ReceiveError::Timeout` if the channel is empty and
+    /// `ReceiveError::Disconnected` if no sender is left to fill it.
+    pub fn try_recv(&self) -> Result<T, ReceiveError> {
+        self.take(false).unwrap_or(Err(ReceiveError::Timeout))
+    }
+
+    /// Block until a value arrives and take it.
+    ///
+    /// Fails with `ReceiveError::Disconnected` once the channel is empty
+    /// and every sender was dropped.
+    pub fn recv(&self) -> Result<T, ReceiveError> {
+        super::wait_until(|park| self.take(park), None).and_then(|r| r)
+    }
+
+    /// Wait up to `ticks` kernel ticks for a value, see [`recv`].
+    ///
+    /// [`recv`]: Receiver::recv
+    pub fn recv_timeout(&self, ticks: u32) -> Result<T, ReceiveError> {
+        super::wait_until(|park| self.take(park), Some(ticks)).and_then(|r| r)
+    }
+
+    /// Pop the oldest value, see `take_matching` in the parent module for
+    /// what `park` does.
+    fn take(&self, park: bool) -> Option<Result<T, ReceiveError>> {
+        let res: Option<Result<T, ReceiveError>>;
+        unsafe {
+            let cs = critical_section::acquire();
+            let shared = &mut *self.shared;
+            res = match shared.queue.pop_front() {
+                Some(v) => Some(Ok(v)),
+                None if shared.senders == 0 => Some(Err(ReceiveError::Disconnected)),
+                None => {
+                    if park {
+                        shared.waiting = Some(thread::get_current_pid());
+                        thread::park(thread::current_idx(), thread::ThreadStatus::MailPending);
+                    }
+                    None
+                }
+            };
+            critical_section::release(cs)
+        }
+        res
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let cs = critical_section::acquire();
+            let shared = &mut *self.shared;
+            shared.receiver = false;
+            shared.waiting = None;
+            let queued = core::mem::take(&mut shared.queue);
+            let free = shared.senders == 0;
+            critical_section::release(cs);
+            drop(queued);
+            if free {
+                drop(Box::from_raw(self.shared));
+            }
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! Message passing between processes.
+//!
+//! # Ordering
+//! Every process has one mailbox, a FIFO queue. Receiving takes the oldest
+//! message first; selective receives take the oldest message that matches
+//! and leave everything they skip in place.
+//!
+//! Messages sent from one process to another are always received in the
+//! order they were sent, including when the two run on different cores.
+//! A send appends to the mailbox inside the kernel's critical section,
+//! which is shared by both cores, so sends are ordered by when they took
+//! it. Messages from different senders interleave in that same order.
+//!
+//! # Capacity
+//! Mailboxes are unbounded by default. A bounded one is set up with a
+//! [`MailboxConfig`] when the thread is created, its [`Overflow`] policy
+//! decides what a send to a full mailbox does.
+//!
+//! # Senders
+//! Every message remembers who sent it, see [`receive_with_sender`].
+//! That is the sending process' [`Pid`], or [`Pid::INTERRUPT`] and
+//! [`Pid::KERNEL`] for messages from interrupt handlers and from before
+//! the scheduler started.
+//!
+//! # Channels
+//! Mailbox messages are `Box<dyn Any>` and have to be downcast. A
+//! [`channel`] carries a single type instead, checked at compile time.
+extern crate alloc;
+
+use core::any::{Any, TypeId};
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::thread::{self, Pid};
+
+mod channel;
+pub use channel::{channel, Receiver, Sender};
+
+// Init needed for static allocation
+const INIT: Mailbox = Mailbox::new();
+
+// Mailbox is kept seperate due to lovely Rust memory initialisation hoops
+static mut ALKYN_MAILBOX: [Mailbox; super::MAX_THREADS] = [INIT; super::MAX_THREADS];
+
+#[derive(Clone, Copy)]
+pub struct RawMessage {
+    msg: *mut dyn Any,
+    from: Pid,
+}
+
+/// What a send to a full mailbox does
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Overflow {
+    /// Park the sender until the receiver takes a message. Senders that
+    /// can not wait, interrupt handlers, code running before the scheduler
+    /// and a process sending to itself, get `SendError::Full` instead.
+    Block,
+    /// Fail the send with `SendError::Full`
+    Fail,
+    /// Drop the oldest queued message to make room
+    DropOldest,
+    /// Drop the message being sent
+    DropNewest,
+}
+
+/// Size and overflow policy of a thread's mailbox
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub struct MailboxConfig {
+    /// Most messages queued at once, `None` for no limit
+    pub capacity: Option<usize>,
+    pub overflow: Overflow,
+}
+
+impl MailboxConfig {
+    pub const UNBOUNDED: MailboxConfig = MailboxConfig {
+        capacity: None,
+        overflow: Overflow::Block,
+    };
+
+    pub const fn bounded(capacity: usize, overflow: Overflow) -> MailboxConfig {
+        MailboxConfig {
+            capacity: Some(capacity),
+            overflow,
+        }
+    }
+}
+
+/// A process' queue of messages, oldest first.
+struct Mailbox {
+    queue: VecDeque<RawMessage>,
+    config: MailboxConfig,
+    /// Messages discarded by the overflow policy
+    dropped: u32,
+    /// Senders parked until there is room, see `Overflow::Block`
+    blocked: Vec<Pid>,
+}
+
+impl Mailbox {
+    const fn new() -> Mailbox {
+        Mailbox {
+            queue: VecDeque::new(),
+            config: MailboxConfig::UNBOUNDED,
+            dropped: 0,
+            blocked: Vec::new(),
+        }
+    }
+
+    /// Queue `msg`, applying the overflow policy if full.
+    ///
+    /// Hands the message back if it has to wait or be refused.
+    fn push(&mut self, msg: RawMessage) -> Result<(), RawMessage> {
+        let full = match self.config.capacity {
+            Some(capacity) => self.queue.len() >= capacity,
+            None => false,
+        };
+        if full {
+            match self.config.overflow {
+                Overflow::Block | Overflow::Fail => return Err(msg),
+                Overflow::DropOldest => {
+                    if let Some(oldest) = self.queue.pop_front() {
+                        drop(unsafe { Box::from_raw(oldest.msg) });
+                    }
+                    self.dropped = self.dropped.wrapping_add(1);
+                }
+                Overflow::DropNewest => {
+                    drop(unsafe { Box::from_raw(msg.msg) });
+                    self.dropped = self.dropped.wrapping_add(1);
+                    return Ok(());
+                }
+            }
+        }
+        self.queue.push_back(msg);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<RawMessage> {
+        self.queue.pop_front()
+    }
+
+    /// Remove the oldest message `pred` accepts.
+    fn take_first<F>(&mut self, pred: &F) -> Option<RawMessage>
+    where
+        F: Fn(&dyn Any) -> bool,
+    {
+        let pos = self.queue.iter().position(|m| pred(unsafe { &*m.msg }))?;
+        self.queue.remove(pos)
+    }
+
+    fn clear(&mut self) {
+        for m in self.queue.drain(..) {
+            drop(unsafe { Box::from_raw(m.msg) });
+        }
+    }
+}
+
+/// Why a message could not be sent
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum SendError {
+    /// The process has exited or was killed
+    NoProcess,
+    /// The mailbox is full and its policy is `Overflow::Fail`, or the
+    /// sender can not wait for `Overflow::Block`
+    Full,
+    /// The channel's [`Receiver`] was dropped
+    Closed,
+}
+
+/// Why nothing was received
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ReceiveError {
+    /// No message arrived before the deadline
+    Timeout,
+    /// The channel is empty and every [`Sender`] was dropped
+    Disconnected,
+}
+
+pub struct Message<T> {
+    msg: Box<T>,
+    _type_id: TypeId,
+}
+
+impl<T> Message<T>
+where
+    T: 'static,
+{
+    pub fn new(p: T) -> Message<T> {
+        Message {
+            _type_id: *&p.type_id(),
+            msg: Box::new(p),
+        }
+    }
+
+    /// Send the message to `pid`.
+    ///
+    /// Fails with `SendError::NoProcess` if `pid` is no longer alive, the
+    /// message is never delivered to a later process reusing its slot.
+    /// If the mailbox is full, its [`Overflow`] policy applies.
+    pub fn send(self, pid: Pid) -> Result<Pid, SendError> {
+        self.send_if(pid, || true)
+    }
+
+    /// [`send`](Message::send) as long as `wanted` holds, checked in the
+    /// same critical section the message is queued in. Fails with
+    /// `SendError::NoProcess` once it does not.
+    pub(crate) fn send_if(self, pid: Pid, wanted: impl Fn() -> bool) -> Result<Pid, SendError> {
+        // Box up our stuff
+        let b: Box<dyn Any> = Box::new(*self.msg);
+        let mut raw = RawMessage {
+            msg: Box::into_raw(b),
+            from: sender(),
+        };
+        let idx = pid.idx();
+        let can_wait = raw.from != Pid::INTERRUPT && raw.from != Pid::KERNEL && raw.from != pid;
+        unsafe {
+            let cs = loop {
+                let cs = critical_section::acquire();
+                if !thread::is_alive(pid) || !wanted() {
+                    critical_section::release(cs);
+                    drop(Box::from_raw(raw.msg));
+                    return Err(SendError::NoProcess);
+                }
+                match ALKYN_MAILBOX[idx].push(raw) {
+                    Ok(()) => break cs,
+                    Err(m) => {
+                        if ALKYN_MAILBOX[idx].config.overflow == Overflow::Fail || !can_wait {
+                            critical_section::release(cs);
+                            drop(Box::from_raw(m.msg));
+                            return Err(SendError::Full);
+                        }
+                        // Parked in the same critical section as the push, so
+                        // a receive right after still wakes us
+                        ALKYN_MAILBOX[idx].blocked.push(m.from);
+                        super::park(m.from.idx(), super::ThreadStatus::SendPending);
+                        critical_section::release(cs);
+                        raw = m;
+                        super::link::stop_if_dead();
+                        thread::systick::run_ctxswitch();
+                    }
+                }
+            };
+
+            let preempt = wake(idx);
+            critical_section::release(cs);
+            if preempt {
+                thread::systick::run_ctxswitch();
+            }
+        };
+        Ok(pid)
+    }
+}
+
+/// Queue `msg` for `to` as if `from` had sent it, regardless of the
+/// mailbox's capacity.
+///
+/// For notifications from the kernel, call inside a critical section.
+pub(crate) fn notify<T: 'static>(to: Pid, from: Pid, msg: T) {
+    let b: Box<dyn Any> = Box::new(msg);
+    unsafe {
+        ALKYN_MAILBOX[to.idx()].queue.push_back(RawMessage {
+            msg: Box::into_raw(b),
+            from,
+        });
+    }
+    wake(to.idx());
+}
+
+/// Who a message sent right now comes from.
+fn sender() -> Pid {
+    if crate::processor::in_interrupt() {
+        Pid::INTERRUPT
+    } else if unsafe { !super::ALKYN_THREADS_GLOBAL.inited } {
+        Pid::KERNEL
+    } else {
+        thread::get_current_pid()
+    }
+}
+
+/// Make the thread in slot `idx` ready if it is parked in a receive.
+///
+/// Call inside a critical section. Returns whether it should preempt the
+/// current thread, which the caller does once it has left the section.
+fn wake(idx: usize) -> bool {
+    let handler = unsafe { &mut super::ALKYN_THREADS_GLOBAL };
+
+    if handler.threads[idx].status == super::ThreadStatus::MailPending {
+        handler.threads[idx].status = super::ThreadStatus::Ready;
+
+        return handler.threads[idx].priority
+            > handler.threads[thread::current_idx()].priority;
+    }
+    false
+}
+
+/// Make the senders parked on the mailbox of slot `idx` ready, to try
+/// again now that it has room or its owner is gone.
+///
+/// Call inside a critical section. Returns whether one of them should
+/// preempt the current thread, as for [`wake`].
+fn wake_senders(idx: usize) -> bool {
+    let handler = unsafe { &mut super::ALKYN_THREADS_GLOBAL };
+    let priority = handler.threads[thread::current_idx()].priority;
+
+    let mut preempt = false;
+    for pid in unsafe { ALKYN_MAILBOX[idx].blocked.drain(..) } {
+        if !thread::is_alive(pid) {
+            continue;
+        }
+        let thr = &mut handler.threads[pid.idx()];
+        if thr.status == super::ThreadStatus::SendPending {
+            thr.status = super::ThreadStatus::Ready;
+            preempt |= thr.priority > priority;
+        }
+    }
+    preempt
+}
+
+/// Drop every message waiting for the thread in slot `idx`.
+pub(crate) fn clear_mailbox(idx: usize) {
+    unsafe {
+        let cs = critical_section::acquire();
+        ALKYN_MAILBOX[idx].clear();
+        wake_senders(idx);
+        critical_section::release(cs)
+    }
+}
+
+/// Set up an empty mailbox for a new thread in slot `idx`.
+pub(crate) fn configure_mailbox(idx: usize, config: MailboxConfig) {
+    unsafe {
+        let cs = critical_section::acquire();
+        let mailbox = &mut ALKYN_MAILBOX[idx];
+        mailbox.clear();
+        wake_senders(idx);
+        mailbox.config = config;
+        mailbox.dropped = 0;
+        critical_section::release(cs)
+    }
+}
+
+/// How many messages to `pid` its mailbox's overflow policy has dropped.
+///
+/// `None` if `pid` is no longer alive.
+pub fn dropped_count(pid: Pid) -> Option<u32> {
+    let res: Option<u32>;
+    unsafe {
+        let cs = critical_section::acquire();
+        res = match thread::is_alive(pid) {
+            true => Some(ALKYN_MAILBOX[pid.idx()].dropped),
+            false => None,
+        };
+        critical_section::release(cs)
+    }
+    res
+}
+
+/// Take the oldest message, if there is one.
+pub fn check_receive() -> Option<Box<dyn Any>> {
+    let current_thread = super::get_current_thread_idx();
+    let msg: Option<RawMessage>;
+    unsafe {
+        let cs = critical_section::acquire();
+        msg = ALKYN_MAILBOX[current_thread].pop();
+        let preempt = msg.is_some() && wake_senders(current_thread);
+        critical_section::release(cs);
+        if preempt {
+            thread::systick::run_ctxswitch();
+        }
+    };
+
+    match msg {
+        Some(m) => {
+            let hydrated_msg = unsafe { Box::from_raw(m.msg) };
+            Some(hydrated_msg)
+        }
+        None => None,
+    }
+}
+
+/// Take the oldest message and who sent it, if there is one.
+pub fn check_receive_with_sender() -> Option<(Pid, Box<dyn Any>)> {
+    take_matching(&|_: &dyn Any| true, false)
+}
+
+/// Block until a message arrives, returning it and who sent it.
+pub fn receive_with_sender() -> (Pid, Box<dyn Any>) {
+    wait_until(|park| take_matching(&|_: &dyn Any| true, park), None).unwrap()
+}
+
+/// Wait up to `ticks` kernel ticks for a message, returning it and who
+/// sent it, see [`receive_timeout`].
+pub fn receive_with_sender_timeout(ticks: u32) -> Result<(Pid, Box<dyn Any>), ReceiveError> {
+    wait_until(|park| take_matching(&|_: &dyn Any| true, park), Some(ticks))
+}
+
+/// Block until a message arrives and take the oldest one.
+pub fn receive() -> Box<dyn Any> {
+    receive_matching(|_| true)
+}
+
+/// Take the first message, in arrival order, that `pred` accepts.
+///
+/// Messages it skips stay in the mailbox in their original order.
+/// `pred` runs inside a critical section, so keep it short.
+pub fn check_receive_matching<F>(pred: F) -> Option<Box<dyn Any>>
+where
+    F: Fn(&dyn Any) -> bool,
+{
+    take_matching(&pred, false).map(|(_, m)| m)
+}
+
+/// Block until a message `pred` accepts arrives, see [`check_receive_matching`].
+pub fn receive_matching<F>(pred: F) -> Box<dyn Any>
+where
+    F: Fn(&dyn Any) -> bool,
+{
+    wait_until(|park| take_matching(&pred, park), None).unwrap().1
+}
+
+/// Wait up to `ticks` kernel ticks for a message.
+///
+/// The thread sleeps as `MailPending` and is woken by either a new message
+/// or the tick that hits the deadline. A `ticks` of 0 never blocks.
+pub fn receive_timeout(ticks: u32) -> Result<Box<dyn Any>, ReceiveError> {
+    receive_matching_timeout(|_| true, ticks)
+}
+
+/// Wait up to `ticks` kernel ticks for a message `pred` accepts, see
+/// [`check_receive_matching`] and [`receive_timeout`].
+pub fn receive_matching_timeout<F>(pred: F, ticks: u32) -> Result<Box<dyn Any>, ReceiveError>
+where
+    F: Fn(&dyn Any) -> bool,
+{
+    wait_until(|park| take_matching(&pred, park), Some(ticks)).map(|(_, m)| m)
+}
+
+/// Park the current thread until `take` hands something over, or until
+/// `ticks` run out. `None` waits forever and `Some(0)` never blocks.
+///
+/// When `take(true)` comes back empty it must mark the thread
+/// `MailPending` within the same critical section, see [`take_matching`].
+fn wait_until<R, F>(take: F, ticks: Option<u32>) -> Result<R, ReceiveError>
+where
+    F: Fn(bool) -> Option<R>,
+{
+    if ticks == Some(0) {
+        return take(false).ok_or(ReceiveError::Timeout);
+    }
+
+    let idx = super::get_current_thread_idx();
+    set_receive_ticks(idx, ticks);
+    let res = loop {
+        match take(true) {
+            Some(m) => break Ok(m),
+            None => {
+                // Safety: only ever written to by this thread and the tick
+                if unsafe { super::ALKYN_THREADS_GLOBAL.threads[idx].timed_out } {
+                    break Err(ReceiveError::Timeout);
+                }
+                super::link::stop_if_dead();
+                // Not `sleep`, that would overwrite the tick count
+                thread::systick::run_ctxswitch();
+            }
+        }
+    };
+    set_receive_ticks(idx, None);
+    res
+}
+
+fn set_receive_ticks(idx: usize, ticks: Option<u32>) {
+    unsafe {
+        let cs = critical_section::acquire();
+        let thr = &mut super::ALKYN_THREADS_GLOBAL.threads[idx];
+        thr.receive_ticks = ticks;
+        thr.timed_out = false;
+        // We may have given up while still parked
+        if thr.status == super::ThreadStatus::MailPending {
+            thr.status = super::ThreadStatus::Ready;
+        }
+        critical_section::release(cs)
+    }
+}
+
+/// Take the first message of type `T`, if there is one.
+pub fn check_receive_of<T: 'static>() -> Option<Box<T>> {
+    check_receive_matching(|m| m.is::<T>()).map(|m| m.downcast().unwrap())
+}
+
+/// Block until a message of type `T` arrives.
+pub fn receive_of<T: 'static>() -> Box<T> {
+    receive_matching(|m| m.is::<T>()).downcast().unwrap()
+}
+
+/// Remove the first message matching `pred` from the current thread's
+/// mailbox.
+///
+/// With `park` set and nothing matching, the thread is marked
+/// `MailPending` in the same critical section, so a message sent right
+/// after the scan still wakes it.
+fn take_matching<F>(pred: &F, park: bool) -> Option<(Pid, Box<dyn Any>)>
+where
+    F: Fn(&dyn Any) -> bool,
+{
+    let current_thread = super::get_current_thread_idx();
+    let msg: Option<RawMessage>;
+    unsafe {
+        let cs = critical_section::acquire();
+        msg = match ALKYN_MAILBOX[current_thread].take_first(pred) {
+            Some(m) => Some(m),
+            None => {
+                if park {
+                    super::park(current_thread, super::ThreadStatus::MailPending);
+                }
+                None
+            }
+        };
+        let preempt = msg.is_some() && wake_senders(current_thread);
+        critical_section::release(cs);
+        if preempt {
+            thread::systick::run_ctxswitch();
+        }
+    };
+
+    msg.map(|m| (m.from, unsafe { Box::from_raw(m.msg) }))
+}
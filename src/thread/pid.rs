@@ -0,0 +1,52 @@
+//! Process identifiers
+//!
+//! A `Pid` names one process for its whole life. Thread slots are reused
+//! once a thread is killed, so alongside the slot index every `Pid` carries
+//! the slot's generation, which is bumped on every reuse. A `Pid` kept
+//! around after its process died will never match the slot's new owner.
+//!
+//! Messages that do not come from a process are marked as sent by one of
+//! the reserved pids [`Pid::KERNEL`] or [`Pid::INTERRUPT`]. These never
+//! name a live process.
+use defmt::Format;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Format)]
+pub struct Pid {
+    idx: u16,
+    generation: u16,
+}
+
+impl Pid {
+    /// Sender of messages sent before the scheduler started.
+    pub const KERNEL: Pid = Pid {
+        idx: u16::MAX,
+        generation: 0,
+    };
+
+    /// Sender of messages sent from an interrupt handler.
+    pub const INTERRUPT: Pid = Pid {
+        idx: u16::MAX - 1,
+        generation: 0,
+    };
+
+    pub(crate) const fn new(idx: usize, generation: u16) -> Pid {
+        Pid {
+            idx: idx as u16,
+            generation,
+        }
+    }
+
+    /// Index of the thread slot this process lives (or lived) in.
+    pub fn idx(&self) -> usize {
+        self.idx as usize
+    }
+
+    pub(crate) fn generation(&self) -> u16 {
+        self.generation
+    }
+
+    /// Whether this names a process rather than the kernel or an interrupt.
+    pub fn is_process(&self) -> bool {
+        *self != Pid::KERNEL && *self != Pid::INTERRUPT
+    }
+}
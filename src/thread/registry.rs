@@ -1,23 +1,35 @@
 //! Registry
 //!
+//! Maps names to the [`Pid`] of a process.
 extern crate alloc;
 use alloc::collections::BTreeMap;
 
-static mut ALKYN_REGISTRY: BTreeMap<&str, usize> = BTreeMap::new();
+use super::Pid;
 
-pub fn set_registry_for_idx(idx: usize, name: &'static str) {
+static mut ALKYN_REGISTRY: BTreeMap<&str, Pid> = BTreeMap::new();
+
+pub fn set_registry_for_pid(pid: Pid, name: &'static str) {
+    unsafe {
+        let cs = critical_section::acquire();
+        ALKYN_REGISTRY.insert(name, pid);
+        critical_section::release(cs);
+    }
+}
+
+/// Remove every name registered for `pid`.
+pub fn unregister_pid(pid: Pid) {
     unsafe {
         let cs = critical_section::acquire();
-        ALKYN_REGISTRY.insert(name, idx);
+        ALKYN_REGISTRY.retain(|_, p| *p != pid);
         critical_section::release(cs);
     }
 }
 
-pub fn lookup_by_idx(idx: usize) -> Option<&'static str> {
+pub fn lookup_by_pid(pid: Pid) -> Option<&'static str> {
     let res: Option<&'static str>;
     unsafe {
         let cs = critical_section::acquire();
-        let lookup = ALKYN_REGISTRY.iter().filter(|(_, i)| i == &&idx).last();
+        let lookup = ALKYN_REGISTRY.iter().filter(|(_, p)| p == &&pid).last();
 
         res = match lookup {
             Some((s, _)) => Some(s.clone()),
@@ -28,8 +40,8 @@ pub fn lookup_by_idx(idx: usize) -> Option<&'static str> {
     res
 }
 
-pub fn lookup_by_name(name: &str) -> Option<usize> {
-    let res: Option<usize>;
+pub fn lookup_by_name(name: &str) -> Option<Pid> {
+    let res: Option<Pid>;
     unsafe {
         let cs = critical_section::acquire();
         res = ALKYN_REGISTRY.get(name).cloned();
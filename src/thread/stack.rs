@@ -0,0 +1,58 @@
+//! Thread stacks
+//!
+//! A thread either runs on memory the caller hands over, usually a
+//! `static mut` array, or on a stack the kernel allocates from the heap.
+//! An allocated stack is returned to the heap once its thread has exited
+//! and been switched out.
+extern crate alloc;
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::ptr;
+
+/// Words of heap stack for the processes the crate starts itself: servers,
+/// state machines, event managers, agents and tasks
+pub(crate) const PROCESS_STACK: usize = 1024;
+
+/// Where a thread's stack lives
+pub enum Stack {
+    /// Memory owned by the caller
+    Static(&'static mut [u32]),
+    /// Allocated by the kernel, this many words long
+    Heap(usize),
+}
+
+impl From<&'static mut [u32]> for Stack {
+    fn from(stack: &'static mut [u32]) -> Stack {
+        Stack::Static(stack)
+    }
+}
+
+impl<const N: usize> From<&'static mut [u32; N]> for Stack {
+    fn from(stack: &'static mut [u32; N]) -> Stack {
+        Stack::Static(stack)
+    }
+}
+
+/// Stacks have to be 8 byte aligned on exception entry
+fn layout(words: usize) -> Layout {
+    Layout::from_size_align(words * 4, 8).unwrap()
+}
+
+/// Allocate a stack of `words` words, `None` if the heap is exhausted.
+///
+/// `words` is rounded up to an even count so the initial stack pointer,
+/// at the top of the stack, stays 8 byte aligned.
+pub(crate) fn allocate(words: usize) -> Option<*mut [u32]> {
+    let words = (words + 1) & !1;
+    let base = unsafe { alloc(layout(words)) } as *mut u32;
+    if base.is_null() {
+        return None;
+    }
+    Some(ptr::slice_from_raw_parts_mut(base, words))
+}
+
+/// Return a stack from [`allocate`] to the heap.
+///
+/// Safety: no thread may still run on it.
+pub(crate) unsafe fn free(stack: *mut [u32]) {
+    dealloc(stack as *mut u8, layout(stack.len()));
+}
@@ -1,20 +1,17 @@
-use crate::{multi, processor};
-use cortex_m::peripheral::syst::SystClkSource;
-use cortex_m::peripheral::SYST;
-use cortex_m_rt::exception;
+use crate::{multi, port, processor};
 use defmt::panic;
 
 use super::ALKYN_THREADS_GLOBAL;
 
 static mut __ALKYN_SYST_ENABLE: bool = false;
 
-#[exception]
-fn SysTick() {
+/// Kernel tick, called by the port from the tick interrupt on core 0.
+pub fn on_tick() {
     defmt::trace!("systick - iv call");
     let cs = unsafe { critical_section::acquire() };
     let handler = unsafe { &mut ALKYN_THREADS_GLOBAL };
     if handler.inited {
-        let count = SYST::get_current();
+        let count = port::tick_current();
         if count > handler.prev_cnt {
             handler.counter = handler.counter + count as u64 + (u32::MAX - handler.prev_cnt) as u64
         } else {
@@ -39,39 +36,36 @@ fn ctxswitch() {
     // Safety: We're inside our critical section
     let handler = unsafe { &mut ALKYN_THREADS_GLOBAL };
     let core_state = &mut handler.cores[curr_core];
+    let mut switch = false;
     if handler.inited {
 
         if core_state.current == core_state.next {
 
             defmt::trace!("systick - getting next thr idx");
             core_state.idx = super::get_next_thread_idx();
+            // Claim it so the other core can't pick it as well
+            handler.threads[core_state.idx].core = super::Core::from_index(curr_core);
             unsafe {
                 core_state.next = core::intrinsics::transmute(&handler.threads[core_state.idx])
             }
         }
-        if core_state.current != core_state.next {
-            unsafe {
-                defmt::trace!("systick - setting pendsv");
-                critical_section::release(cs);
-                processor::set_pendsv();
-            }
-        }
+        switch = core_state.current != core_state.next;
     }
 
     unsafe { critical_section::release(cs) }
+    if switch {
+        defmt::trace!("systick - setting pendsv");
+        unsafe { processor::set_pendsv() }
+    }
 }
 
-pub fn enable(syst: &mut SYST, reload: u32) {
+pub fn enable(syst: &mut port::Systick, reload: u32) {
     let cs = unsafe { critical_section::acquire() };
 
     // Safety: within critical section
     unsafe {
         if !__ALKYN_SYST_ENABLE {
-            syst.set_clock_source(SystClkSource::Core);
-            syst.set_reload(reload);
-            syst.clear_current();
-            syst.enable_counter();
-            syst.enable_interrupt();
+            port::enable_tick(syst, reload);
             __ALKYN_SYST_ENABLE = true;
         } else {
             panic!("Tried to enable twice")
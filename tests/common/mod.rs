@@ -0,0 +1,105 @@
+//! Harness shared by the hosted tests.
+//!
+//! The kernel can only be started once per process, so every test declared
+//! with [`kernel_test!`] re-runs its own test binary with only itself
+//! selected. In that child the test body runs as the first process of a
+//! fresh kernel, privileged so it can spawn whatever it needs, and the test
+//! passes once the body returns. A panic anywhere in the kernel fails it.
+#![allow(dead_code)]
+
+use std::process::Command;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use alkyn::port::Systick;
+use alkyn::thread::{self, msg, msg::MailboxConfig, registry, Core, Pid, Stack};
+
+/// Set in the child to the name of the test it runs
+const ISOLATED: &str = "ALKYN_ISOLATED_TEST";
+
+/// How long a test body may run before it is failed
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Declare a `#[test]` whose body runs in a kernel of its own.
+///
+/// With `#[before_start(setup)]`, `setup` is called with the test
+/// process's `Pid` after it is created, before the kernel is started.
+macro_rules! kernel_test {
+    (#[before_start($setup:path)] $(#[$attr:meta])* fn $name:ident() $body:block) => {
+        $(#[$attr])*
+        #[test]
+        fn $name() {
+            common::isolated(stringify!($name), Some($setup), || $body);
+        }
+    };
+    ($(#[$attr:meta])* fn $name:ident() $body:block) => {
+        $(#[$attr])*
+        #[test]
+        fn $name() {
+            common::isolated(stringify!($name), None, || $body);
+        }
+    };
+}
+
+/// The process running the test body.
+pub fn test_pid() -> Pid {
+    registry::lookup_by_name("test").expect("no test process")
+}
+
+/// Send `what` to the test process, e.g. to tell it what a process did.
+pub fn report<T: 'static>(what: T) {
+    msg::Message::new(what).send(test_pid()).unwrap();
+}
+
+/// Run `body` in a fresh kernel, see the module documentation.
+///
+/// `name` has to be the name of the calling test.
+pub fn isolated(name: &str, setup: Option<fn(Pid)>, body: fn()) {
+    if std::env::var(ISOLATED).as_deref() == Ok(name) {
+        return run_in_kernel(setup, body);
+    }
+
+    let exe = std::env::current_exe().expect("no test binary");
+    let out = Command::new(exe)
+        .args([name, "--exact", "--nocapture", "--test-threads=1"])
+        .env(ISOLATED, name)
+        .output()
+        .expect("could not re-run the test binary");
+    assert!(
+        out.status.success(),
+        "{} failed in its kernel:\n{}{}",
+        name,
+        String::from_utf8_lossy(&out.stdout),
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+fn run_in_kernel(setup: Option<fn(Pid)>, body: fn()) {
+    let (done, finished) = mpsc::channel();
+
+    alkyn::init();
+    let test = thread::spawn_with_config(
+        "test",
+        Stack::Heap(1024),
+        move || {
+            body();
+            done.send(()).unwrap();
+            loop {
+                thread::sleep(100);
+            }
+        },
+        0x01,
+        true,
+        Core::None,
+        MailboxConfig::UNBOUNDED,
+    )
+    .expect("could not create the test process");
+    if let Some(setup) = setup {
+        setup(test);
+    }
+    std::thread::spawn(|| alkyn::start(&mut Systick, 80_000));
+
+    finished
+        .recv_timeout(TEST_TIMEOUT)
+        .expect("test body did not finish");
+}
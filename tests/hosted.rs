@@ -0,0 +1,55 @@
+//! Kernel behaviour on the hosted port, run with `cargo test-hosted`.
+//!
+//! Tests declared with `kernel_test!` each run in a kernel of their own, see
+//! `common`.
+#[macro_use]
+mod common;
+
+use alkyn::thread::{self, msg, registry, Pid};
+
+kernel_test! {
+    fn message_is_delivered_between_threads() {
+        thread::spawn("receiver", thread::Stack::Heap(128), || {
+            let m = msg::receive().downcast::<u32>().expect("not a u32");
+            common::report(*m + 1);
+        })
+        .unwrap();
+        thread::spawn("sender", thread::Stack::Heap(128), || {
+            let receiver = registry::lookup_by_name("receiver").unwrap();
+            msg::Message::new(42u32).send(receiver).unwrap();
+        })
+        .unwrap();
+
+        assert_eq!(*msg::receive_of::<u32>(), 43);
+    }
+}
+
+kernel_test! {
+    fn messages_carry_the_pid_of_their_sender() {
+        let sender = thread::spawn("sender", thread::Stack::Heap(128), || {
+            common::report(());
+        })
+        .unwrap();
+
+        let (from, m) = msg::receive_with_sender();
+        assert!(m.is::<()>());
+        assert_eq!(from, sender);
+        assert!(from.is_process());
+    }
+}
+
+fn queue_before_start(test: Pid) {
+    msg::Message::new(7u32).send(test).unwrap();
+}
+
+kernel_test! {
+    #[before_start(queue_before_start)]
+    fn messages_queued_before_start_come_from_the_kernel() {
+        let (from, m) = msg::receive_with_sender();
+        assert_eq!(*m.downcast::<u32>().unwrap(), 7);
+        assert_eq!(from, Pid::KERNEL);
+        assert!(!from.is_process());
+        assert!(!Pid::INTERRUPT.is_process());
+        assert!(!thread::is_alive(Pid::KERNEL) && !thread::is_alive(Pid::INTERRUPT));
+    }
+}
@@ -0,0 +1,56 @@
+//! Typed channels on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use alkyn::thread::msg::{self, ReceiveError, SendError};
+use alkyn::thread::{self, Stack};
+
+kernel_test! {
+    fn recv_blocks_until_a_value_is_sent() {
+        let (tx, rx) = msg::channel::<u32>();
+        thread::spawn("sender", Stack::Heap(128), move || {
+            thread::sleep(5);
+            tx.send(7).unwrap();
+        })
+        .unwrap();
+
+        let start = thread::get_ticks();
+        assert_eq!(rx.recv(), Ok(7));
+        assert!(thread::get_ticks().wrapping_sub(start) >= 5);
+    }
+}
+
+kernel_test! {
+    fn recv_is_disconnected_once_every_sender_is_dropped() {
+        let (tx, rx) = msg::channel::<u32>();
+        let tx2 = tx.clone();
+        thread::spawn("sender", Stack::Heap(128), move || {
+            tx.send(1).unwrap();
+            tx2.send(2).unwrap();
+        })
+        .unwrap();
+
+        // Queued values are still handed out first
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+        assert_eq!(rx.recv(), Err(ReceiveError::Disconnected));
+        assert_eq!(rx.try_recv(), Err(ReceiveError::Disconnected));
+    }
+}
+
+kernel_test! {
+    fn recv_timeout_gives_up_while_senders_remain() {
+        let (_tx, rx) = msg::channel::<u32>();
+        assert_eq!(rx.recv_timeout(5), Err(ReceiveError::Timeout));
+        assert_eq!(rx.try_recv(), Err(ReceiveError::Timeout));
+    }
+}
+
+kernel_test! {
+    fn send_is_closed_once_the_receiver_is_dropped() {
+        let (tx, rx) = msg::channel::<u32>();
+        tx.send(1).unwrap();
+        drop(rx);
+        assert_eq!(tx.send(2), Err(SendError::Closed));
+    }
+}
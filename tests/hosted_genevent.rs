@@ -0,0 +1,45 @@
+//! Event managers on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use alkyn::genevent::{self, EventHandler};
+use alkyn::thread::{self, msg, ExitReason};
+
+use common::report;
+
+/// Tells the test what it handled, panics on `0`
+struct Handler(&'static str);
+
+impl EventHandler<u32> for Handler {
+    fn handle_event(&mut self, event: &u32) -> Result<(), ExitReason> {
+        if *event == 0 && self.0 == "panicking" {
+            panic!("handler failed");
+        }
+        report((self.0, *event));
+        Ok(())
+    }
+
+    fn terminate(&mut self, reason: ExitReason) {
+        report((self.0, reason));
+    }
+}
+
+kernel_test! {
+    fn a_panicking_handler_is_removed_and_the_manager_keeps_running() {
+        let manager = genevent::start::<u32>("events").unwrap();
+        genevent::add_handler(manager, Handler("panicking"), 100).unwrap();
+        let steady = genevent::add_handler(manager, Handler("steady"), 100).unwrap();
+
+        genevent::sync_notify(manager, 0u32, 100).unwrap();
+        assert_eq!(
+            *msg::receive_of::<(&str, ExitReason)>(),
+            ("panicking", ExitReason::Error("handler panicked"))
+        );
+        assert_eq!(*msg::receive_of::<(&str, u32)>(), ("steady", 0));
+        assert_eq!(genevent::which_handlers::<u32>(manager, 100), Ok(vec![steady]));
+
+        genevent::sync_notify(manager, 1u32, 100).unwrap();
+        assert_eq!(*msg::receive_of::<(&str, u32)>(), ("steady", 1));
+        assert!(thread::is_alive(manager));
+    }
+}
@@ -0,0 +1,305 @@
+//! GenServers on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use std::any::Any;
+
+use alkyn::genserver::{self, CallError, GenServer, NoReply, Reply, Then};
+use alkyn::sys;
+use alkyn::thread::{self, msg, registry, Down, ExitReason, Pid, Stack};
+
+use common::report;
+
+struct Server;
+
+enum Call {
+    Echo(u32),
+    /// Reply after sleeping this many ticks
+    Slow(u32),
+    /// Stop without replying
+    Crash,
+}
+
+enum Cast {
+    /// Time out if no message arrives within this many ticks
+    Idle(u32),
+    /// Continue with the given name before the next message
+    Continue(&'static str),
+    Ping,
+}
+
+impl GenServer for Server {
+    /// Pings handled
+    type State = u32;
+    type Call = Call;
+    type CallReply = u32;
+    type Cast = Cast;
+    type Continue = &'static str;
+
+    fn get_name() -> &'static str {
+        "server"
+    }
+
+    fn init(self) -> Result<u32, ExitReason> {
+        Ok(0)
+    }
+
+    fn handle_call(
+        request: Call,
+        _from: genserver::From<u32>,
+        state: u32,
+    ) -> Reply<u32, u32, &'static str> {
+        match request {
+            Call::Echo(n) => Reply::Reply(n, state),
+            Call::Slow(ticks) => {
+                thread::sleep(ticks);
+                Reply::Reply(ticks, state)
+            }
+            Call::Crash => Reply::Stop(ExitReason::Error("crash"), state),
+        }
+    }
+
+    fn handle_cast(request: Cast, state: u32) -> NoReply<u32, &'static str> {
+        match request {
+            Cast::Idle(ticks) => NoReply::NoReplyThen(state, Then::Timeout(ticks)),
+            Cast::Continue(name) => NoReply::NoReplyThen(state, Then::Continue(name)),
+            Cast::Ping => {
+                report("ping");
+                NoReply::NoReply(state + 1)
+            }
+        }
+    }
+
+    fn handle_continue(name: &'static str, state: u32) -> NoReply<u32, &'static str> {
+        report(name);
+        match name {
+            "first" => NoReply::NoReplyThen(state, Then::Continue("second")),
+            _ => NoReply::NoReply(state),
+        }
+    }
+
+    fn handle_timeout(state: u32) -> NoReply<u32, &'static str> {
+        report("timeout");
+        NoReply::NoReply(state)
+    }
+
+    fn handle_info(msg: Box<dyn Any>, from: Pid, state: u32) -> NoReply<u32, &'static str> {
+        if let Ok(n) = msg.downcast::<u32>() {
+            report((*n, from));
+        }
+        NoReply::NoReply(state)
+    }
+
+    fn terminate(reason: ExitReason, _state: u32) {
+        report(reason);
+    }
+}
+
+kernel_test! {
+    fn call_times_out_without_a_reply() {
+        let server = Server.start().unwrap();
+        assert_eq!(
+            genserver::call::<Server>(server, Call::Slow(20), 5),
+            Err(CallError::Timeout)
+        );
+
+        // The late reply is dropped, not left in the mailbox
+        thread::sleep(30);
+        assert!(msg::check_receive().is_none());
+        assert_eq!(genserver::call::<Server>(server, Call::Echo(3), 100), Ok(3));
+    }
+}
+
+kernel_test! {
+    fn call_to_a_stopped_server_is_server_down() {
+        let server = Server.start().unwrap();
+        let monitor_ref = thread::monitor(server);
+        genserver::stop::<Server>(server, ExitReason::Normal).unwrap();
+        assert_eq!(msg::receive_of::<Down>().monitor_ref, monitor_ref);
+
+        assert_eq!(
+            genserver::call::<Server>(server, Call::Echo(1), 100),
+            Err(CallError::ServerDown(ExitReason::NoProc))
+        );
+    }
+}
+
+kernel_test! {
+    fn call_fails_when_the_server_stops_before_replying() {
+        let server = Server.start().unwrap();
+        assert_eq!(
+            genserver::call::<Server>(server, Call::Crash, 100),
+            Err(CallError::ServerDown(ExitReason::Error("crash")))
+        );
+        assert_eq!(*msg::receive_of::<ExitReason>(), ExitReason::Error("crash"));
+        assert!(msg::check_receive().is_none());
+    }
+}
+
+kernel_test! {
+    fn then_timeout_fires_when_no_message_arrives() {
+        let server = Server.start().unwrap();
+        let start = thread::get_ticks();
+        genserver::cast::<Server>(server, Cast::Idle(5)).unwrap();
+
+        assert_eq!(*msg::receive_of::<&str>(), "timeout");
+        assert!(thread::get_ticks().wrapping_sub(start) >= 5);
+    }
+}
+
+kernel_test! {
+    fn then_timeout_is_cancelled_by_the_next_message() {
+        let server = Server.start().unwrap();
+        genserver::cast::<Server>(server, Cast::Idle(10)).unwrap();
+        genserver::cast::<Server>(server, Cast::Ping).unwrap();
+
+        assert_eq!(*msg::receive_of::<&str>(), "ping");
+        thread::sleep(20);
+        assert!(msg::check_receive().is_none());
+    }
+}
+
+kernel_test! {
+    fn then_continue_runs_before_the_next_message() {
+        let server = Server.start().unwrap();
+        genserver::cast::<Server>(server, Cast::Continue("first")).unwrap();
+        genserver::cast::<Server>(server, Cast::Ping).unwrap();
+
+        let reports: Vec<&str> = (0..3).map(|_| *msg::receive_of::<&str>()).collect();
+        assert_eq!(reports, vec!["first", "second", "ping"]);
+    }
+}
+
+kernel_test! {
+    fn get_state_returns_a_snapshot() {
+        let server = Server.start().unwrap();
+        genserver::cast::<Server>(server, Cast::Ping).unwrap();
+        genserver::cast::<Server>(server, Cast::Ping).unwrap();
+
+        assert_eq!(sys::get_state::<Server, _>(server, |pings| *pings > 0, 100), Ok(true));
+        assert_eq!(sys::get_state::<Server, _>(server, u32::clone, 100), Ok(2));
+    }
+}
+
+kernel_test! {
+    fn replace_state_swaps_the_state() {
+        let server = Server.start().unwrap();
+        genserver::cast::<Server>(server, Cast::Ping).unwrap();
+
+        assert_eq!(sys::replace_state::<Server, _>(server, |pings| pings + 10, 100), Ok(()));
+        assert_eq!(sys::get_state::<Server, _>(server, u32::clone, 100), Ok(11));
+    }
+}
+
+kernel_test! {
+    fn suspend_holds_requests_until_resume() {
+        let server = Server.start().unwrap();
+        assert_eq!(sys::suspend::<Server>(server, 100), Ok(()));
+        genserver::cast::<Server>(server, Cast::Ping).unwrap();
+
+        // System messages are still answered, the cast waits
+        assert_eq!(sys::get_state::<Server, _>(server, u32::clone, 100), Ok(0));
+        thread::sleep(10);
+        assert!(msg::check_receive().is_none());
+
+        assert_eq!(sys::resume::<Server>(server, 100), Ok(()));
+        assert_eq!(*msg::receive_of::<&str>(), "ping");
+        assert_eq!(sys::get_state::<Server, _>(server, u32::clone, 100), Ok(1));
+    }
+}
+
+kernel_test! {
+    fn system_messages_keep_the_then_timeout_deadline() {
+        let server = Server.start().unwrap();
+        genserver::cast::<Server>(server, Cast::Idle(20)).unwrap();
+
+        // Each would restart the timeout if it did not keep the deadline
+        for _ in 0..6 {
+            thread::sleep(5);
+            sys::get_state::<Server, _>(server, u32::clone, 100).unwrap();
+        }
+        assert_eq!(msg::check_receive_of::<&str>().map(|m| *m), Some("timeout"));
+    }
+}
+
+kernel_test! {
+    fn each_server_has_its_own_process_and_state() {
+        let named = Server.start().unwrap();
+        let anonymous = Server.start_anonymous().unwrap();
+        assert_ne!(named, anonymous);
+        assert_eq!(registry::lookup_by_name("server"), Some(named));
+
+        genserver::cast::<Server>(named, Cast::Ping).unwrap();
+        genserver::cast::<Server>(named, Cast::Ping).unwrap();
+        genserver::cast::<Server>(anonymous, Cast::Ping).unwrap();
+        assert_eq!(sys::get_state::<Server, _>(named, u32::clone, 100), Ok(2));
+        assert_eq!(sys::get_state::<Server, _>(anonymous, u32::clone, 100), Ok(1));
+    }
+}
+
+kernel_test! {
+    fn other_messages_go_to_handle_info() {
+        let server = Server.start().unwrap();
+        msg::Message::new(7u32).send(server).unwrap();
+        assert_eq!(*msg::receive_of::<(u32, Pid)>(), (7, thread::get_current_pid()));
+    }
+}
+
+kernel_test! {
+    fn stop_runs_terminate_with_its_reason() {
+        let server = Server.start().unwrap();
+        genserver::stop::<Server>(server, ExitReason::Error("stopped")).unwrap();
+        assert_eq!(*msg::receive_of::<ExitReason>(), ExitReason::Error("stopped"));
+    }
+}
+
+/// Holds a call back until a cast tells it to answer
+struct Deferred;
+
+impl GenServer for Deferred {
+    /// The call held back and its request
+    type State = Option<(genserver::From<u32>, u32)>;
+    type Call = u32;
+    type CallReply = u32;
+    type Cast = ();
+
+    fn get_name() -> &'static str {
+        "deferred"
+    }
+
+    fn init(self) -> Result<Self::State, ExitReason> {
+        Ok(None)
+    }
+
+    fn handle_call(
+        n: u32,
+        from: genserver::From<u32>,
+        _state: Self::State,
+    ) -> Reply<Self::State, u32> {
+        Reply::NoReply(Some((from, n)))
+    }
+
+    fn handle_cast(_request: (), state: Self::State) -> NoReply<Self::State> {
+        if let Some((from, n)) = state {
+            genserver::reply(from, n + 1);
+        }
+        NoReply::NoReply(None)
+    }
+}
+
+kernel_test! {
+    fn handle_call_can_reply_later() {
+        let server = Deferred.start().unwrap();
+        thread::spawn("caller", Stack::Heap(256), move || {
+            report(genserver::call::<Deferred>(server, 1, 1000));
+        })
+        .unwrap();
+
+        // Still waiting until the server is told to answer
+        thread::sleep(10);
+        assert!(msg::check_receive().is_none());
+
+        genserver::cast::<Deferred>(server, ()).unwrap();
+        assert_eq!(*msg::receive_of::<Result<u32, CallError>>(), Ok(2));
+    }
+}
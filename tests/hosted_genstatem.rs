@@ -0,0 +1,156 @@
+//! State machines on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use alkyn::genserver;
+use alkyn::genstatem::{self, Event, GenStateMachine, Transition};
+use alkyn::thread::{self, msg, ExitReason, Stack};
+
+use common::report;
+
+#[derive(Clone, PartialEq)]
+enum State {
+    Locked,
+    Open,
+}
+
+struct Door;
+
+enum Cast {
+    EventTimeout(u32),
+    StateTimeout(u32),
+    Ping,
+    Unlock,
+    /// Postponed while locked, reported once handled
+    Record(u32),
+}
+
+impl GenStateMachine for Door {
+    type State = State;
+    /// Times unlocked
+    type Data = u32;
+    type Call = ();
+    type CallReply = u32;
+    type Cast = Cast;
+
+    fn get_name() -> &'static str {
+        "door"
+    }
+
+    fn init(self) -> Result<(State, u32), ExitReason> {
+        Ok((State::Locked, 0))
+    }
+
+    fn postpone(event: &Event<Self>, state: &State, _data: &u32) -> bool {
+        *state == State::Locked && matches!(event, Event::Call(..) | Event::Cast(Cast::Record(_)))
+    }
+
+    fn handle_event(event: Event<Self>, _state: &State, data: u32) -> Transition<State, u32> {
+        match event {
+            Event::Cast(Cast::EventTimeout(ticks)) => Transition::keep(data).event_timeout(ticks),
+            Event::Cast(Cast::StateTimeout(ticks)) => Transition::keep(data).state_timeout(ticks),
+            Event::Cast(Cast::Ping) => {
+                report("ping");
+                Transition::keep(data)
+            }
+            Event::Cast(Cast::Unlock) => Transition::next(State::Open, data + 1),
+            Event::Cast(Cast::Record(n)) => {
+                report(n);
+                Transition::keep(data)
+            }
+            Event::Call((), from) => {
+                genserver::reply(from, data);
+                Transition::keep(data)
+            }
+            Event::EventTimeout => {
+                report("event timeout");
+                Transition::keep(data)
+            }
+            Event::StateTimeout => {
+                report("state timeout");
+                Transition::keep(data)
+            }
+            Event::Info(..) | Event::Enter(_) => Transition::keep(data),
+        }
+    }
+}
+
+kernel_test! {
+    fn event_timeout_fires_when_no_event_arrives() {
+        let door = Door.start().unwrap();
+        let start = thread::get_ticks();
+        genstatem::cast::<Door>(door, Cast::EventTimeout(5)).unwrap();
+
+        assert_eq!(*msg::receive_of::<&str>(), "event timeout");
+        assert!(thread::get_ticks().wrapping_sub(start) >= 5);
+    }
+}
+
+kernel_test! {
+    fn event_timeout_is_cancelled_by_the_next_event() {
+        let door = Door.start().unwrap();
+        genstatem::cast::<Door>(door, Cast::EventTimeout(10)).unwrap();
+        genstatem::cast::<Door>(door, Cast::Ping).unwrap();
+
+        assert_eq!(*msg::receive_of::<&str>(), "ping");
+        thread::sleep(20);
+        assert!(msg::check_receive().is_none());
+    }
+}
+
+kernel_test! {
+    fn a_zero_event_timeout_gives_way_to_a_queued_event() {
+        let door = Door.start().unwrap();
+        genstatem::cast::<Door>(door, Cast::EventTimeout(0)).unwrap();
+        genstatem::cast::<Door>(door, Cast::Ping).unwrap();
+
+        assert_eq!(*msg::receive_of::<&str>(), "ping");
+        thread::sleep(10);
+        assert!(msg::check_receive().is_none());
+    }
+}
+
+kernel_test! {
+    fn state_timeout_outlasts_events_in_the_same_state() {
+        let door = Door.start().unwrap();
+        genstatem::cast::<Door>(door, Cast::StateTimeout(10)).unwrap();
+        for _ in 0..3 {
+            genstatem::cast::<Door>(door, Cast::Ping).unwrap();
+        }
+
+        let reports: Vec<&str> = (0..4).map(|_| *msg::receive_of::<&str>()).collect();
+        assert_eq!(reports, vec!["ping", "ping", "ping", "state timeout"]);
+    }
+}
+
+kernel_test! {
+    fn state_timeout_is_cancelled_by_a_state_change() {
+        let door = Door.start().unwrap();
+        genstatem::cast::<Door>(door, Cast::StateTimeout(10)).unwrap();
+        genstatem::cast::<Door>(door, Cast::Unlock).unwrap();
+
+        thread::sleep(20);
+        assert!(msg::check_receive().is_none());
+    }
+}
+
+kernel_test! {
+    fn postponed_events_are_handled_in_order_after_a_state_change() {
+        let door = Door.start().unwrap();
+        thread::spawn("caller", Stack::Heap(256), move || {
+            report(genstatem::call::<Door>(door, (), 1000));
+        })
+        .unwrap();
+        genstatem::cast::<Door>(door, Cast::Record(1)).unwrap();
+        genstatem::cast::<Door>(door, Cast::Record(2)).unwrap();
+
+        // Nothing is handled while locked
+        thread::sleep(10);
+        assert!(msg::check_receive().is_none());
+
+        genstatem::cast::<Door>(door, Cast::Unlock).unwrap();
+        assert_eq!(*msg::receive_of::<Result<u32, genserver::CallError>>(), Ok(1));
+        assert_eq!(*msg::receive_of::<u32>(), 1);
+        assert_eq!(*msg::receive_of::<u32>(), 2);
+    }
+}
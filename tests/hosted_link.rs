@@ -0,0 +1,136 @@
+//! Links and exit signals on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+use alkyn::thread::msg::{MailboxConfig, SendError};
+use alkyn::thread::{self, msg, Core, Exit, ExitReason, Pid, Stack};
+
+/// Spawn a process that links to `to`, if given, tells the test and then
+/// waits for a message that never comes.
+fn spawn_linked(name: &'static str, to: Option<Pid>) -> Pid {
+    let pid = thread::spawn(name, Stack::Heap(128), move || {
+        if let Some(to) = to {
+            thread::link(to);
+        }
+        common::report(name);
+        msg::receive_of::<()>();
+    })
+    .unwrap();
+    assert_eq!(*msg::receive_of::<&str>(), name);
+    pid
+}
+
+kernel_test! {
+    fn abnormal_exit_spreads_along_links() {
+        thread::trap_exit(true);
+        let c = spawn_linked("c", None);
+        let b = spawn_linked("b", Some(c));
+        thread::link(b);
+
+        thread::send_exit(c, ExitReason::Error("boom"));
+        let exit = msg::receive_of::<Exit>();
+        assert_eq!(*exit, Exit { pid: b, reason: ExitReason::Error("boom") });
+        assert!(!thread::is_alive(b));
+        assert!(!thread::is_alive(c));
+    }
+}
+
+kernel_test! {
+    fn normal_exit_leaves_links_running() {
+        thread::trap_exit(true);
+        let c = spawn_linked("c", None);
+        let b = spawn_linked("b", Some(c));
+        thread::link(c);
+
+        msg::Message::new(()).send(c).unwrap();
+        let exit = msg::receive_of::<Exit>();
+        assert_eq!(*exit, Exit { pid: c, reason: ExitReason::Normal });
+        assert!(thread::is_alive(b));
+    }
+}
+
+kernel_test! {
+    fn trapped_exit_signals_arrive_as_messages() {
+        thread::trap_exit(true);
+        let sender = thread::spawn("sender", Stack::Heap(128), || {
+            thread::send_exit(common::test_pid(), ExitReason::Error("stop"));
+        })
+        .unwrap();
+
+        let exit = msg::receive_of::<Exit>();
+        assert_eq!(*exit, Exit { pid: sender, reason: ExitReason::Error("stop") });
+    }
+}
+
+kernel_test! {
+    fn kill_can_not_be_trapped() {
+        thread::trap_exit(true);
+        let target = thread::spawn("target", Stack::Heap(128), || {
+            thread::trap_exit(true);
+            common::report("trapping");
+            loop {
+                // Only ever an `Exit`, which must not come
+                msg::receive();
+            }
+        })
+        .unwrap();
+        assert_eq!(*msg::receive_of::<&str>(), "trapping");
+        thread::link(target);
+
+        thread::send_exit(target, ExitReason::Kill);
+        let exit = msg::receive_of::<Exit>();
+        assert_eq!(*exit, Exit { pid: target, reason: ExitReason::Killed });
+    }
+}
+
+kernel_test! {
+    fn linking_to_a_dead_process_signals_noproc() {
+        thread::trap_exit(true);
+        let dead = thread::spawn("dead", Stack::Heap(128), || ()).unwrap();
+        while thread::is_alive(dead) {
+            thread::sleep(1);
+        }
+
+        thread::link(dead);
+        let exit = msg::receive_of::<Exit>();
+        assert_eq!(*exit, Exit { pid: dead, reason: ExitReason::NoProc });
+    }
+}
+
+static SPINNING: AtomicBool = AtomicBool::new(false);
+static GO: AtomicBool = AtomicBool::new(false);
+
+kernel_test! {
+    fn a_process_killed_while_running_stays_dead() {
+        let pid = thread::spawn_with_config(
+            "spinner",
+            Stack::Heap(128),
+            || {
+                SPINNING.store(true, SeqCst);
+                while !GO.load(SeqCst) {}
+                // Already killed, must not park as if alive
+                msg::receive_of::<()>();
+            },
+            0x03,
+            false,
+            Core::Core1,
+            MailboxConfig::UNBOUNDED,
+        )
+        .unwrap();
+        while !SPINNING.load(SeqCst) {
+            thread::sleep(1);
+        }
+
+        // Still running on the other core when it is killed
+        thread::send_exit(pid, ExitReason::Kill);
+        GO.store(true, SeqCst);
+        thread::sleep(5);
+
+        assert!(!thread::is_alive(pid));
+        assert_eq!(msg::Message::new(()).send(pid).err(), Some(SendError::NoProcess));
+        thread::sleep(5);
+        assert!(!thread::is_alive(pid));
+    }
+}
@@ -0,0 +1,203 @@
+//! Mailboxes on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use alkyn::thread::msg::{MailboxConfig, Overflow, ReceiveError, SendError};
+use alkyn::thread::{self, msg, Core, Pid, Stack};
+
+const BURST: u32 = 50;
+
+fn send_burst(sender: u8) {
+    for seq in 0..BURST {
+        common::report((sender, seq));
+    }
+}
+
+kernel_test! {
+    fn messages_arrive_in_send_order_per_sender() {
+        // One sender per core
+        for (name, sender, core) in [("sender0", 0, Core::Core0), ("sender1", 1, Core::Core1)] {
+            thread::spawn_with_config(
+                name,
+                Stack::Heap(128),
+                move || send_burst(sender),
+                0x02,
+                false,
+                core,
+                MailboxConfig::UNBOUNDED,
+            )
+            .unwrap();
+        }
+
+        let received: Vec<(u8, u32)> =
+            (0..2 * BURST).map(|_| *msg::receive_of::<(u8, u32)>()).collect();
+        for sender in 0..2 {
+            let seqs: Vec<u32> = received
+                .iter()
+                .filter(|(s, _)| *s == sender)
+                .map(|&(_, seq)| seq)
+                .collect();
+            assert_eq!(seqs, (0..BURST).collect::<Vec<u32>>());
+        }
+    }
+}
+
+/// Spawn a process with a `config` mailbox that takes nothing from it until
+/// told to go, then sends the test the `count` numbers it takes.
+fn spawn_holding(config: MailboxConfig, count: usize) -> (Pid, msg::Sender<()>) {
+    let (go, wait) = msg::channel::<()>();
+    let pid = thread::spawn_with_config(
+        "holding",
+        Stack::Heap(256),
+        move || {
+            wait.recv().unwrap();
+            let taken: Vec<u32> = (0..count).map(|_| *msg::receive_of::<u32>()).collect();
+            common::report(taken);
+        },
+        0x01,
+        false,
+        Core::None,
+        config,
+    )
+    .unwrap();
+    (pid, go)
+}
+
+kernel_test! {
+    fn overflow_fail_refuses_sends_to_a_full_mailbox() {
+        let (pid, go) = spawn_holding(MailboxConfig::bounded(2, Overflow::Fail), 2);
+        let sent: Vec<_> = (0..3u32)
+            .map(|i| msg::Message::new(i).send(pid).map(|_| ()))
+            .collect();
+        assert_eq!(sent, vec![Ok(()), Ok(()), Err(SendError::Full)]);
+        assert_eq!(msg::dropped_count(pid), Some(0));
+
+        go.send(()).unwrap();
+        assert_eq!(*msg::receive_of::<Vec<u32>>(), vec![0, 1]);
+    }
+}
+
+kernel_test! {
+    fn overflow_drop_oldest_keeps_the_latest_messages() {
+        let (pid, go) = spawn_holding(MailboxConfig::bounded(2, Overflow::DropOldest), 2);
+        for i in 0..5u32 {
+            msg::Message::new(i).send(pid).unwrap();
+        }
+        assert_eq!(msg::dropped_count(pid), Some(3));
+
+        go.send(()).unwrap();
+        assert_eq!(*msg::receive_of::<Vec<u32>>(), vec![3, 4]);
+    }
+}
+
+kernel_test! {
+    fn overflow_drop_newest_keeps_the_first_messages() {
+        let (pid, go) = spawn_holding(MailboxConfig::bounded(2, Overflow::DropNewest), 2);
+        for i in 0..5u32 {
+            msg::Message::new(i).send(pid).unwrap();
+        }
+        assert_eq!(msg::dropped_count(pid), Some(3));
+
+        go.send(()).unwrap();
+        assert_eq!(*msg::receive_of::<Vec<u32>>(), vec![0, 1]);
+    }
+}
+
+kernel_test! {
+    fn overflow_block_parks_the_sender_until_there_is_room() {
+        let (pid, go) = spawn_holding(MailboxConfig::bounded(1, Overflow::Block), 3);
+        thread::spawn("sender", Stack::Heap(128), move || {
+            for i in 0..3u32 {
+                msg::Message::new(i).send(pid).unwrap();
+            }
+            common::report("sent");
+        })
+        .unwrap();
+
+        // Still parked on the second message
+        thread::sleep(10);
+        assert!(msg::check_receive_of::<&str>().is_none());
+        assert_eq!(msg::dropped_count(pid), Some(0));
+
+        go.send(()).unwrap();
+        assert_eq!(*msg::receive_of::<Vec<u32>>(), vec![0, 1, 2]);
+        assert_eq!(*msg::receive_of::<&str>(), "sent");
+    }
+}
+
+kernel_test! {
+    fn overflow_block_fails_a_send_to_itself() {
+        thread::spawn_with_config(
+            "self",
+            Stack::Heap(128),
+            || {
+                let me = thread::get_current_pid();
+                let sent: Vec<_> = (0..2u32)
+                    .map(|i| msg::Message::new(i).send(me).map(|_| ()))
+                    .collect();
+                common::report(sent);
+            },
+            0x01,
+            false,
+            Core::None,
+            MailboxConfig::bounded(1, Overflow::Block),
+        )
+        .unwrap();
+
+        let sent = msg::receive_of::<Vec<Result<(), SendError>>>();
+        assert_eq!(*sent, vec![Ok(()), Err(SendError::Full)]);
+    }
+}
+
+kernel_test! {
+    fn receive_timeout_gives_up_at_the_deadline() {
+        let start = thread::get_ticks();
+        assert_eq!(msg::receive_timeout(5).err(), Some(ReceiveError::Timeout));
+        assert!(thread::get_ticks().wrapping_sub(start) >= 5);
+
+        // A timeout of 0 only polls
+        assert_eq!(msg::receive_timeout(0).err(), Some(ReceiveError::Timeout));
+    }
+}
+
+kernel_test! {
+    fn receive_timeout_returns_a_message_sent_in_time() {
+        thread::spawn("sender", Stack::Heap(128), || {
+            thread::sleep(5);
+            common::report(7u32);
+        })
+        .unwrap();
+
+        let m = msg::receive_timeout(1000).expect("timed out");
+        assert_eq!(*m.downcast::<u32>().unwrap(), 7);
+    }
+}
+
+kernel_test! {
+    fn receive_matching_timeout_leaves_other_messages() {
+        let me = thread::get_current_pid();
+        msg::Message::new(8u32).send(me).unwrap();
+
+        let r = msg::receive_matching_timeout(|m| m.is::<u8>(), 5);
+        assert_eq!(r.err(), Some(ReceiveError::Timeout));
+        assert_eq!(msg::check_receive_of::<u32>().map(|m| *m), Some(8));
+    }
+}
+
+kernel_test! {
+    fn receive_matching_waits_and_keeps_other_messages_in_order() {
+        thread::spawn("sender", Stack::Heap(128), || {
+            common::report(1u32);
+            common::report(2u32);
+            thread::sleep(5);
+            common::report("match");
+            common::report(3u32);
+        })
+        .unwrap();
+
+        let m = msg::receive_matching(|m| m.is::<&str>());
+        assert_eq!(*m.downcast::<&str>().unwrap(), "match");
+        let rest: Vec<u32> = (0..3).map(|_| *msg::receive().downcast::<u32>().unwrap()).collect();
+        assert_eq!(rest, vec![1, 2, 3]);
+    }
+}
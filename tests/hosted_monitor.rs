@@ -0,0 +1,68 @@
+//! Monitors on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use alkyn::thread::msg::{self, ReceiveError};
+use alkyn::thread::{self, Down, ExitReason, Pid, Stack};
+
+/// Spawn a process that exits normally once it is sent `()`.
+fn spawn_waiting() -> Pid {
+    thread::spawn("target", Stack::Heap(128), || {
+        msg::receive_of::<()>();
+    })
+    .unwrap()
+}
+
+kernel_test! {
+    fn down_carries_the_exit_reason() {
+        let target = spawn_waiting();
+        let monitor_ref = thread::monitor(target);
+
+        thread::send_exit(target, ExitReason::Error("boom"));
+        let down = msg::receive_of::<Down>();
+        assert_eq!(
+            *down,
+            Down { pid: target, reason: ExitReason::Error("boom"), monitor_ref }
+        );
+    }
+}
+
+kernel_test! {
+    fn each_monitor_gets_its_own_down() {
+        let target = spawn_waiting();
+        let first = thread::monitor(target);
+        let second = thread::monitor(target);
+        assert_ne!(first, second);
+
+        msg::Message::new(()).send(target).unwrap();
+        let refs: Vec<_> = (0..2).map(|_| msg::receive_of::<Down>().monitor_ref).collect();
+        assert_eq!(refs, vec![first, second]);
+    }
+}
+
+kernel_test! {
+    fn monitoring_a_dead_process_sends_noproc() {
+        let dead = thread::spawn("dead", Stack::Heap(128), || ()).unwrap();
+        while thread::is_alive(dead) {
+            thread::sleep(1);
+        }
+
+        let monitor_ref = thread::monitor(dead);
+        let down = msg::receive_of::<Down>();
+        assert_eq!(*down, Down { pid: dead, reason: ExitReason::NoProc, monitor_ref });
+    }
+}
+
+kernel_test! {
+    fn demonitor_stops_the_down() {
+        let target = spawn_waiting();
+        let dropped = thread::monitor(target);
+        let kept = thread::monitor(target);
+        thread::demonitor(dropped);
+
+        thread::send_exit(target, ExitReason::Kill);
+        assert_eq!(msg::receive_of::<Down>().monitor_ref, kept);
+        let more = msg::receive_matching_timeout(|m| m.is::<Down>(), 10);
+        assert_eq!(more.err(), Some(ReceiveError::Timeout));
+    }
+}
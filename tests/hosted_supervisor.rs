@@ -0,0 +1,210 @@
+//! Supervisors on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use alkyn::supervisor::{ChildError, ChildSpec, DynamicSupervisor, Restart, Strategy, Supervisor};
+use alkyn::thread::msg::{self, ReceiveError};
+use alkyn::thread::{self, registry, Down, ExitReason, Pid, Stack};
+
+static mut SUP_STACK: [u32; 256] = [0; 256];
+static mut CHILD_STACKS: [[u32; 256]; 3] = [[0; 256]; 3];
+
+/// Sent to the test by every child that starts
+struct Started(u8, Pid);
+
+/// Child body: reports that it started and waits until it is stopped.
+fn child<const ID: u8>() {
+    common::report(Started(ID, thread::get_current_pid()));
+    msg::receive_of::<()>();
+}
+
+/// How to start the child `id`.
+fn spec(id: usize) -> ChildSpec {
+    let stack = unsafe { &mut CHILD_STACKS[id] };
+    match id {
+        0 => ChildSpec::new("child0", child::<0>, stack),
+        1 => ChildSpec::new("child1", child::<1>, stack),
+        _ => ChildSpec::new("child2", child::<2>, stack),
+    }
+}
+
+/// Wait for `count` children to start, returns which by id.
+///
+/// Children of the same priority do not run in the order they were started.
+fn started(count: usize) -> Vec<(u8, Pid)> {
+    let mut started: Vec<(u8, Pid)> = (0..count)
+        .map(|_| {
+            let started = msg::receive_of::<Started>();
+            (started.0, started.1)
+        })
+        .collect();
+    started.sort_by_key(|s| s.0);
+    started
+}
+
+/// Check no other child starts for a while.
+fn none_started() {
+    let r = msg::receive_matching_timeout(|m| m.is::<Started>(), 20);
+    assert_eq!(r.err(), Some(ReceiveError::Timeout));
+}
+
+/// Start a supervisor of three children, returns it and their pids.
+fn start_supervisor(strategy: Strategy, max_restarts: u32) -> (Pid, Vec<Pid>) {
+    let children = (0..3).map(spec).collect();
+    let sup = Supervisor::new(strategy, max_restarts, 1000, children)
+        .start("sup", unsafe { &mut SUP_STACK })
+        .unwrap();
+
+    let started = started(3);
+    assert_eq!(started.iter().map(|s| s.0).collect::<Vec<u8>>(), vec![0, 1, 2]);
+    (sup, started.iter().map(|s| s.1).collect())
+}
+
+/// Kill the second child, returns which children started again by id.
+fn kill_second_child(strategy: Strategy) -> Vec<u8> {
+    let (_, pids) = start_supervisor(strategy, 3);
+    thread::send_exit(pids[1], ExitReason::Kill);
+    let mut restarted = Vec::new();
+    while let Ok(m) = msg::receive_matching_timeout(|m| m.is::<Started>(), 20) {
+        restarted.push(m.downcast::<Started>().unwrap().0);
+    }
+    restarted.sort();
+    restarted
+}
+
+kernel_test! {
+    fn one_for_one_restarts_only_the_child_that_died() {
+        assert_eq!(kill_second_child(Strategy::OneForOne), vec![1]);
+    }
+}
+
+kernel_test! {
+    fn one_for_all_restarts_every_child() {
+        assert_eq!(kill_second_child(Strategy::OneForAll), vec![0, 1, 2]);
+    }
+}
+
+kernel_test! {
+    fn rest_for_one_restarts_the_children_started_after_it() {
+        assert_eq!(kill_second_child(Strategy::RestForOne), vec![1, 2]);
+    }
+}
+
+kernel_test! {
+    fn restarted_child_keeps_its_pid_and_name() {
+        let (_, pids) = start_supervisor(Strategy::OneForOne, 3);
+        thread::send_exit(pids[1], ExitReason::Kill);
+        assert_eq!(started(1), vec![(1, pids[1])]);
+        assert_eq!(registry::lookup_by_name("child1"), Some(pids[1]));
+    }
+}
+
+kernel_test! {
+    fn too_many_restarts_shut_the_supervisor_down() {
+        let (sup, pids) = start_supervisor(Strategy::OneForOne, 1);
+        let monitor_ref = thread::monitor(sup);
+
+        thread::send_exit(pids[0], ExitReason::Kill);
+        let restarted = started(1);
+        assert_eq!(restarted[0].0, 0);
+
+        thread::send_exit(restarted[0].1, ExitReason::Kill);
+        let down = msg::receive_of::<Down>();
+        assert_eq!(*down, Down { pid: sup, reason: ExitReason::Shutdown, monitor_ref });
+        none_started();
+        assert!(pids.iter().all(|&pid| !thread::is_alive(pid)));
+    }
+}
+
+kernel_test! {
+    fn a_child_that_is_not_restarted_gives_its_slot_back() {
+        let mut transient = spec(0);
+        transient.restart = Restart::Transient;
+        Supervisor::new(Strategy::OneForOne, 3, 1000, vec![transient])
+            .start("sup", unsafe { &mut SUP_STACK })
+            .unwrap();
+        let pid = started(1)[0].1;
+        let monitor_ref = thread::monitor(pid);
+
+        // A normal exit, so it is not restarted
+        msg::Message::new(()).send(pid).unwrap();
+        assert_eq!(msg::receive_of::<Down>().monitor_ref, monitor_ref);
+        none_started();
+
+        let other = thread::spawn("other", Stack::Heap(128), || ()).unwrap();
+        assert_eq!(other.idx(), pid.idx());
+    }
+}
+
+/// Start a dynamic supervisor of at most `max_children` children.
+fn start_dynamic(max_children: usize) -> Pid {
+    DynamicSupervisor::new(3, 1000, max_children)
+        .start("sup", unsafe { &mut SUP_STACK })
+        .unwrap()
+}
+
+kernel_test! {
+    fn dynamic_supervisor_restarts_its_children() {
+        let sup = start_dynamic(2);
+        let pid = DynamicSupervisor::start_child(sup, spec(0)).unwrap();
+        assert_eq!(started(1), vec![(0, pid)]);
+
+        thread::send_exit(pid, ExitReason::Kill);
+        assert_eq!(started(1)[0].0, 0);
+    }
+}
+
+kernel_test! {
+    fn dynamic_supervisor_refuses_more_than_max_children() {
+        let sup = start_dynamic(1);
+        DynamicSupervisor::start_child(sup, spec(0)).unwrap();
+        assert_eq!(
+            DynamicSupervisor::start_child(sup, spec(1)).err(),
+            Some(ChildError::MaxChildren)
+        );
+        started(1);
+        none_started();
+    }
+}
+
+kernel_test! {
+    fn dynamic_supervisor_terminates_a_child_for_good() {
+        let sup = start_dynamic(2);
+        let pid = DynamicSupervisor::start_child(sup, spec(0)).unwrap();
+        started(1);
+        let monitor_ref = thread::monitor(pid);
+
+        assert_eq!(DynamicSupervisor::terminate_child(sup, pid), Ok(()));
+        let down = msg::receive_of::<Down>();
+        assert_eq!(*down, Down { pid, reason: ExitReason::Shutdown, monitor_ref });
+        none_started();
+
+        // Its slot is free for another child
+        DynamicSupervisor::start_child(sup, spec(1)).unwrap();
+        assert_eq!(started(1)[0].0, 1);
+    }
+}
+
+kernel_test! {
+    fn dynamic_supervisor_only_terminates_its_children() {
+        let sup = start_dynamic(2);
+        let pid = DynamicSupervisor::start_child(sup, spec(0)).unwrap();
+        started(1);
+
+        let me = thread::get_current_pid();
+        assert_eq!(DynamicSupervisor::terminate_child(sup, me), Err(ChildError::NotFound));
+        DynamicSupervisor::terminate_child(sup, pid).unwrap();
+        assert_eq!(DynamicSupervisor::terminate_child(sup, pid), Err(ChildError::NotFound));
+    }
+}
+
+kernel_test! {
+    fn dynamic_supervisor_requests_fail_once_it_is_gone() {
+        let sup = start_dynamic(2);
+        thread::send_exit(sup, ExitReason::Kill);
+        assert_eq!(
+            DynamicSupervisor::start_child(sup, spec(0)).err(),
+            Some(ChildError::NoSupervisor)
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Tasks and agents on the hosted port, run with `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use alkyn::agent::Agent;
+use alkyn::task::{Task, TaskError};
+use alkyn::thread::{self, msg, ExitReason, Stack};
+
+kernel_test! {
+    fn join_returns_the_result_of_the_task() {
+        let task = Task::spawn(|| 6 * 7).unwrap();
+        assert_eq!(task.join(100), Ok(42));
+    }
+}
+
+kernel_test! {
+    fn join_reports_a_task_that_exited_without_a_result() {
+        let task = Task::spawn(|| -> u32 { thread::exit(ExitReason::Error("boom")) }).unwrap();
+        assert_eq!(task.join(100), Err(TaskError::Exited(ExitReason::Error("boom"))));
+    }
+}
+
+kernel_test! {
+    fn join_kills_a_task_still_running_at_the_timeout() {
+        let task = Task::spawn(|| {
+            thread::sleep(1000);
+            1u8
+        })
+        .unwrap();
+        let pid = task.pid();
+        assert_eq!(task.join(5), Err(TaskError::Timeout));
+        thread::sleep(2);
+        assert!(!thread::is_alive(pid));
+        assert!(msg::check_receive().is_none());
+    }
+}
+
+kernel_test! {
+    fn agent_runs_closures_on_its_value() {
+        let agent: Agent<Vec<u32>> = Agent::start("agent", || vec![1]).unwrap();
+        agent.update(|mut v| { v.push(2); v }, 100).unwrap();
+        assert_eq!(agent.get_and_update(|mut v| (v.pop(), v), 100), Ok(Some(2)));
+        assert_eq!(agent.get(|v| v.clone(), 100), Ok(vec![1]));
+    }
+}
+
+kernel_test! {
+    fn unprivileged_processes_can_not_start_tasks_or_agents() {
+        thread::spawn("unprivileged", Stack::Heap(512), || {
+            let task = Task::spawn(|| 1u32).err();
+            let agent = Agent::start("agent", || 1u32).err();
+            common::report((task, agent));
+        })
+        .unwrap();
+
+        assert_eq!(*msg::receive_of::<(Option<u8>, Option<u8>)>(), (Some(2), Some(2)));
+    }
+}
+
+#[test]
+fn tasks_fail_before_the_kernel_is_started() {
+    alkyn::init();
+    assert_eq!(Task::spawn(|| ()).err(), Some(7));
+    // Started like any server, it runs once the kernel does
+    assert!(Agent::start("agent", || 0u32).is_ok());
+}
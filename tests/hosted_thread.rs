@@ -0,0 +1,148 @@
+//! Creating and killing threads on the hosted port, run with
+//! `cargo test-hosted`.
+#[macro_use]
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+
+use alkyn::thread::msg::{self, MailboxConfig, SendError};
+use alkyn::thread::{self, Core, Down, ExitReason, Pid, Stack};
+
+static BUSY: AtomicBool = AtomicBool::new(false);
+static STOP: AtomicBool = AtomicBool::new(false);
+static DROPPED: AtomicBool = AtomicBool::new(false);
+
+/// Dropped along with the closure that owns it
+struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        DROPPED.store(true, SeqCst);
+    }
+}
+
+kernel_test! {
+    fn closure_of_a_thread_killed_before_its_first_run_is_freed() {
+        // Hold the second core, so nothing else pinned to it gets to run
+        thread::spawn_with_config(
+            "busy",
+            Stack::Heap(128),
+            || {
+                BUSY.store(true, SeqCst);
+                while !STOP.load(SeqCst) {}
+            },
+            0x03,
+            false,
+            Core::Core1,
+            MailboxConfig::UNBOUNDED,
+        )
+        .unwrap();
+        while !BUSY.load(SeqCst) {
+            thread::sleep(1);
+        }
+
+        let guard = Guard;
+        let pid = thread::spawn_with_config(
+            "never",
+            Stack::Heap(128),
+            move || {
+                drop(guard);
+                panic!("ran after it was killed");
+            },
+            0x01,
+            false,
+            Core::Core1,
+            MailboxConfig::UNBOUNDED,
+        )
+        .unwrap();
+        assert!(!DROPPED.load(SeqCst));
+
+        thread::send_exit(pid, ExitReason::Kill);
+        assert!(DROPPED.load(SeqCst));
+        STOP.store(true, SeqCst);
+    }
+}
+
+kernel_test! {
+    fn a_stale_pid_is_rejected_after_its_slot_is_reused() {
+        let old = thread::spawn("old", Stack::Heap(128), || {
+            msg::receive_of::<()>();
+        })
+        .unwrap();
+        let monitor_ref = thread::monitor(old);
+        msg::Message::new(()).send(old).unwrap();
+        assert_eq!(msg::receive_of::<Down>().monitor_ref, monitor_ref);
+        assert_eq!(msg::Message::new(1u32).send(old).err(), Some(SendError::NoProcess));
+
+        // Switched out by now, so its slot is free again
+        thread::sleep(2);
+        let new = thread::spawn("new", Stack::Heap(128), || {
+            common::report(*msg::receive_of::<u32>());
+        })
+        .unwrap();
+        assert_eq!(new.idx(), old.idx());
+        assert_ne!(new, old);
+
+        assert_eq!(msg::Message::new(1u32).send(old).err(), Some(SendError::NoProcess));
+        msg::Message::new(2u32).send(new).unwrap();
+        assert_eq!(*msg::receive_of::<u32>(), 2);
+    }
+}
+
+kernel_test! {
+    fn restarting_a_pid_without_a_slot_fails() {
+        assert_eq!(thread::restart_thread(Pid::KERNEL), Err(4));
+        assert_eq!(thread::restart_thread(Pid::INTERRUPT), Err(4));
+    }
+}
+
+/// Words of the stack watched by [`Counting`], a size nothing else uses
+const WATCHED_WORDS: usize = 1234;
+static WATCHED: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts live allocations the size of a heap stack of [`WATCHED_WORDS`]
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == WATCHED_WORDS * 4 {
+            WATCHED.fetch_add(1, SeqCst);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() == WATCHED_WORDS * 4 {
+            WATCHED.fetch_sub(1, SeqCst);
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+kernel_test! {
+    fn a_heap_stack_is_given_back_once_its_thread_is_switched_out() {
+        let pid = thread::spawn("short", Stack::Heap(WATCHED_WORDS), || {
+            common::report("ran");
+        })
+        .unwrap();
+        assert_eq!(WATCHED.load(SeqCst), 1);
+        let monitor_ref = thread::monitor(pid);
+        assert_eq!(*msg::receive_of::<&str>(), "ran");
+        assert_eq!(msg::receive_of::<Down>().monitor_ref, monitor_ref);
+
+        // Exited while running, no thread is created after it
+        thread::sleep(2);
+        assert_eq!(WATCHED.load(SeqCst), 0);
+    }
+}
+
+kernel_test! {
+    fn a_heap_stack_that_is_too_small_is_refused() {
+        assert_eq!(thread::spawn("tiny", Stack::Heap(0), || ()).err(), Some(1));
+        assert_eq!(thread::spawn("tiny", Stack::Heap(31), || ()).err(), Some(1));
+    }
+}